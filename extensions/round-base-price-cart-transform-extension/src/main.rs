@@ -1,4 +1,5 @@
 use std::process;
+pub mod rounding;
 pub mod run;
 
 fn main() {
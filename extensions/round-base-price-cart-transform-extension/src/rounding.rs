@@ -0,0 +1,547 @@
+use rust_decimal::Decimal;
+use rust_decimal::RoundingStrategy as MidpointStrategy;
+use serde::{Deserialize, Serialize};
+
+/// Largest scale a `Decimal` can represent.
+const MAX_DECIMAL_PLACES: u32 = 28;
+
+/// ISO 4217 currencies without minor units, e.g. `JPY`.
+const ZERO_DECIMAL_CURRENCIES: [&str; 16] = [
+    "BIF", "CLP", "DJF", "GNF", "ISK", "JPY", "KMF", "KRW", "PYG", "RWF", "UGX", "VND", "VUV", "XAF", "XOF", "XPF",
+];
+
+/// ISO 4217 currencies with three decimals, e.g. `KWD`.
+const THREE_DECIMAL_CURRENCIES: [&str; 7] = ["BHD", "IQD", "JOD", "KWD", "LYD", "OMR", "TND"];
+
+/// How a unit price is brought onto the rounding grid. `down`, `up` and `nearest`
+/// are accepted as aliases, which reads better when snapping to a charm ending.
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RoundingStrategy {
+    /// Round down, e.g. 23.47 -> 23.00.
+    #[default]
+    #[serde(alias = "down")]
+    Floor,
+    /// Round up to the next whole value, e.g. 23.01 -> 24.00.
+    #[serde(alias = "up")]
+    Ceil,
+    /// Round to the nearest value, ties away from zero, e.g. 23.50 -> 24.00.
+    #[serde(alias = "nearest")]
+    HalfUp,
+    /// Round to the nearest value, ties to the even neighbour (banker's rounding), e.g. 22.50 -> 22.00.
+    HalfEven,
+    /// Drop any digits past `decimalPlaces` without rounding, moving the price
+    /// towards zero. Any non-negative price is rounded the same as with `floor`.
+    Truncate,
+}
+
+/// Which amount of a cart line a rule rounds.
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RoundingTarget {
+    /// Round each unit price, e.g. 0.37 -> 0.00.
+    #[default]
+    UnitPrice,
+    /// Round `unit price * quantity` and derive the unit price back from it,
+    /// e.g. 40 x 0.37 = 14.80 -> 14.00, charged as 40 x 0.35.
+    LineTotal,
+}
+
+/// How prices in one currency are rounded.
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Debug)]
+#[serde(rename_all(deserialize = "camelCase"), default)]
+pub struct RoundingRule {
+    #[serde(alias = "direction")]
+    pub strategy: RoundingStrategy,
+    pub decimal_places: u32,
+    /// Step between rounded prices, e.g. `0.05` for CHF cash rounding or `5` for
+    /// whole five-krone amounts. Takes precedence over `decimalPlaces`.
+    pub increment: Option<Decimal>,
+    /// Charm ending every rounded price should finish on, e.g. `0.99` or `9.00`.
+    /// When set, `decimalPlaces` is ignored.
+    pub ending: Option<Decimal>,
+    pub target: RoundingTarget,
+    /// Decimals a unit price may have, e.g. `0` for JPY. Used to split rounded
+    /// line totals and to show prices in line titles. Defaults to the minor
+    /// units of the line's currency.
+    pub unit_decimal_places: Option<u32>,
+    #[serde(flatten)]
+    pub guardrail: Guardrail,
+    /// Leave lines matched by this rule unchanged.
+    pub skip: bool,
+}
+
+/// The result of rounding one cart line.
+#[derive(PartialEq, Clone, Debug)]
+pub struct LineRounding {
+    /// The amount the rule rounds: the unit price, or the line total.
+    pub original: Decimal,
+    /// What `original` becomes once the new unit price is applied.
+    pub rounded: Decimal,
+    /// The fixed price per unit to charge.
+    pub unit_price: Decimal,
+}
+
+/// Limits on how far rounding may move a price. A line whose rounded price
+/// breaks any of them keeps its original price.
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Debug)]
+#[serde(rename_all(deserialize = "camelCase"), default)]
+pub struct Guardrail {
+    /// Largest allowed difference between the original and rounded unit price.
+    pub max_price_change: Option<Decimal>,
+    /// Largest allowed difference as a percentage of the original unit price.
+    pub max_price_change_percent: Option<Decimal>,
+    /// Rounded prices above the original are only emitted when this is set.
+    pub allow_price_increase: bool,
+}
+
+impl Guardrail {
+    /// Keys a guardrail takes in the metafield, next to the keys of its rule.
+    pub const FIELDS: &'static [&'static str] = &["maxPriceChange", "maxPriceChangePercent", "allowPriceIncrease"];
+
+    /// This guardrail with the limits of `ceiling` applied on top, so a rule can
+    /// tighten the top-level limits but never loosen them. Whether prices may
+    /// increase is still up to each rule.
+    pub fn capped_by(&self, ceiling: &Guardrail) -> Guardrail {
+        let lowest = |limit: Option<Decimal>, ceiling: Option<Decimal>| match (limit, ceiling) {
+            (Some(limit), Some(ceiling)) => Some(limit.min(ceiling)),
+            (limit, ceiling) => limit.or(ceiling),
+        };
+
+        Guardrail {
+            max_price_change: lowest(self.max_price_change, ceiling.max_price_change),
+            max_price_change_percent: lowest(self.max_price_change_percent, ceiling.max_price_change_percent),
+            allow_price_increase: self.allow_price_increase,
+        }
+    }
+
+    pub fn allows(&self, original: Decimal, rounded: Decimal) -> bool {
+        if rounded > original && !self.allow_price_increase {
+            return false;
+        }
+
+        let change = (rounded - original).abs();
+
+        if let Some(max_price_change) = self.max_price_change {
+            if change > max_price_change {
+                return false;
+            }
+        }
+
+        if let Some(max_price_change_percent) = self.max_price_change_percent {
+            // Compare change / original against percent / 100 without dividing
+            match (change.checked_mul(Decimal::ONE_HUNDRED), max_price_change_percent.checked_mul(original)) {
+                (Some(change), Some(limit)) if change <= limit => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+impl RoundingRule {
+    /// Keys a rule takes in the metafield besides those of its `Guardrail`,
+    /// including the `direction` alias of `strategy`.
+    pub const FIELDS: &'static [&'static str] =
+        &["strategy", "direction", "decimalPlaces", "increment", "ending", "target", "unitDecimalPlaces", "skip"];
+
+    /// Returns the `(step, offset)` of the grid prices are rounded onto.
+    ///
+    /// A positive `increment` is always the step. Otherwise the grid is every
+    /// `10^-decimalPlaces`, or with an ending the smallest power of ten above it,
+    /// so `0.99` repeats every unit (22.99, 23.99) and `9.00` every ten units
+    /// (19.00, 29.00). Returns `None` for an ending too large to build a grid for.
+    pub fn grid(&self) -> Option<(Decimal, Decimal)> {
+        let increment = self.increment.filter(|increment| increment.is_sign_positive() && !increment.is_zero());
+
+        match (increment, self.ending) {
+            (Some(increment), ending) => Some((increment, ending.unwrap_or(Decimal::ZERO))),
+            (None, Some(ending)) => {
+                let mut step = Decimal::ONE;
+                while step <= ending {
+                    step = step.checked_mul(Decimal::TEN)?;
+                }
+                Some((step, ending))
+            }
+            (None, None) => Some((Decimal::new(1, self.decimal_places.min(MAX_DECIMAL_PLACES)), Decimal::ZERO)),
+        }
+    }
+
+    /// Rounds `price` according to this rule, or `None` if it cannot be done exactly.
+    pub fn round(&self, price: Decimal) -> Option<Decimal> {
+        let (step, offset) = self.grid()?;
+        round_price(price, self.strategy, step, offset)
+    }
+
+    /// Decimals a unit price has: `unitDecimalPlaces` when set, otherwise the
+    /// ISO 4217 minor units of `currency_code`, e.g. 0 for JPY and 3 for KWD.
+    pub fn unit_decimal_places(&self, currency_code: &str) -> u32 {
+        let minor_units = if ZERO_DECIMAL_CURRENCIES.contains(&currency_code) {
+            0
+        } else if THREE_DECIMAL_CURRENCIES.contains(&currency_code) {
+            3
+        } else {
+            2
+        };
+        self.unit_decimal_places.unwrap_or(minor_units).min(MAX_DECIMAL_PLACES)
+    }
+
+    /// Like `round_line`, but never returns a unit price below `floor`. A line
+    /// that would be rounded below it is rounded up instead, or `None` is
+    /// returned if even that stays below the floor.
+    pub fn round_line_above(
+        &self,
+        unit_price: Decimal,
+        quantity: i64,
+        currency_code: &str,
+        floor: Decimal,
+    ) -> Option<LineRounding> {
+        let rounding = self.round_line(unit_price, quantity, currency_code)?;
+        if rounding.unit_price >= floor {
+            return Some(rounding);
+        }
+
+        let rounding_up = RoundingRule { strategy: RoundingStrategy::Ceil, ..self.clone() };
+        rounding_up.round_line(unit_price, quantity, currency_code).filter(|rounding| rounding.unit_price >= floor)
+    }
+
+    /// Rounds a cart line according to `target`.
+    ///
+    /// In `lineTotal` mode a rounded total that cannot be split evenly into the
+    /// unit decimal places of `currency_code` is split with the unit price
+    /// rounded back towards the original, so the charged total ends up as close
+    /// to the rounded total as possible without passing the original. Returns `None` for lines without a
+    /// positive quantity in that mode.
+    pub fn round_line(&self, unit_price: Decimal, quantity: i64, currency_code: &str) -> Option<LineRounding> {
+        match self.target {
+            RoundingTarget::UnitPrice => {
+                let rounded = self.round(unit_price)?;
+                Some(LineRounding { original: unit_price, rounded, unit_price: rounded })
+            }
+            RoundingTarget::LineTotal => {
+                if quantity <= 0 {
+                    return None;
+                }
+
+                let quantity = Decimal::from(quantity);
+                let original = unit_price.checked_mul(quantity)?;
+                let rounded_total = self.round(original)?;
+
+                let towards_original = if rounded_total < original {
+                    MidpointStrategy::ToPositiveInfinity
+                } else {
+                    MidpointStrategy::ToNegativeInfinity
+                };
+                let derived_unit_price = rounded_total
+                    .checked_div(quantity)?
+                    .round_dp_with_strategy(self.unit_decimal_places(currency_code), towards_original)
+                    .normalize();
+
+                Some(LineRounding {
+                    original,
+                    rounded: derived_unit_price.checked_mul(quantity)?,
+                    unit_price: derived_unit_price,
+                })
+            }
+        }
+    }
+}
+
+/// Rounds `price` onto the grid `offset + k * step` using `strategy`.
+///
+/// All arithmetic is exact, so the result never lands on the wrong side of
+/// `price` and never moves by a full `step` or more. Returns `None` when the
+/// calculation would overflow.
+pub fn round_price(price: Decimal, strategy: RoundingStrategy, step: Decimal, offset: Decimal) -> Option<Decimal> {
+    let scaled = price.checked_sub(offset)?.checked_div(step)?;
+
+    let rounded = match strategy {
+        RoundingStrategy::Floor => scaled.floor(),
+        RoundingStrategy::Ceil => scaled.ceil(),
+        RoundingStrategy::HalfUp => scaled.round_dp_with_strategy(0, MidpointStrategy::MidpointAwayFromZero),
+        RoundingStrategy::HalfEven => scaled.round_dp_with_strategy(0, MidpointStrategy::MidpointNearestEven),
+        // Truncate towards zero in terms of the price, not the offset grid, so a
+        // price below a charm ending is never pulled up to it
+        RoundingStrategy::Truncate if price.is_sign_negative() => scaled.ceil(),
+        RoundingStrategy::Truncate => scaled.floor(),
+    };
+
+    Some(rounded.checked_mul(step)?.checked_add(offset)?.normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{quickcheck, TestResult};
+    use rust_decimal_macros::dec;
+
+    const STRATEGIES: [RoundingStrategy; 5] = [
+        RoundingStrategy::Floor,
+        RoundingStrategy::Ceil,
+        RoundingStrategy::HalfUp,
+        RoundingStrategy::HalfEven,
+        RoundingStrategy::Truncate,
+    ];
+
+    const STEPS: [Decimal; 7] = [dec!(0.01), dec!(0.05), dec!(0.1), dec!(0.25), dec!(1), dec!(5), dec!(10)];
+
+    #[test]
+    fn test_round_price_strategies() {
+        assert_eq!(round_price(dec!(23.5), RoundingStrategy::HalfUp, dec!(1), dec!(0)), Some(dec!(24)));
+        assert_eq!(round_price(dec!(22.5), RoundingStrategy::HalfEven, dec!(1), dec!(0)), Some(dec!(22)));
+        assert_eq!(round_price(dec!(23.5), RoundingStrategy::HalfEven, dec!(1), dec!(0)), Some(dec!(24)));
+        assert_eq!(round_price(dec!(23.49), RoundingStrategy::HalfEven, dec!(1), dec!(0)), Some(dec!(23)));
+        assert_eq!(round_price(dec!(23.125), RoundingStrategy::Truncate, dec!(0.1), dec!(0)), Some(dec!(23.1)));
+        assert_eq!(round_price(dec!(23.75), RoundingStrategy::Floor, dec!(1), dec!(0)), Some(dec!(23)));
+    }
+
+    #[test]
+    fn test_round_price_has_no_float_artifacts() {
+        assert_eq!(round_price(dec!(19.999999), RoundingStrategy::Floor, dec!(0.01), dec!(0)), Some(dec!(19.99)));
+        assert_eq!(round_price(dec!(0.3), RoundingStrategy::Floor, dec!(0.1), dec!(0)), Some(dec!(0.3)));
+        assert_eq!(round_price(dec!(23.47), RoundingStrategy::Floor, dec!(0.01), dec!(0)), Some(dec!(23.47)));
+        assert_eq!(round_price(dec!(1.15), RoundingStrategy::HalfUp, dec!(0.1), dec!(0)), Some(dec!(1.2)));
+    }
+
+    #[test]
+    fn test_round_price_to_charm_endings() {
+        assert_eq!(round_price(dec!(23.40), RoundingStrategy::Floor, dec!(1), dec!(0.99)), Some(dec!(22.99)));
+        assert_eq!(round_price(dec!(23.40), RoundingStrategy::Ceil, dec!(1), dec!(0.99)), Some(dec!(23.99)));
+        assert_eq!(round_price(dec!(23.40), RoundingStrategy::HalfUp, dec!(1), dec!(0.49)), Some(dec!(23.49)));
+        assert_eq!(round_price(dec!(23.40), RoundingStrategy::Floor, dec!(1), dec!(0.95)), Some(dec!(22.95)));
+        assert_eq!(round_price(dec!(23.40), RoundingStrategy::Floor, dec!(10), dec!(9)), Some(dec!(19)));
+        assert_eq!(round_price(dec!(23.40), RoundingStrategy::Ceil, dec!(10), dec!(9)), Some(dec!(29)));
+    }
+
+    #[test]
+    fn test_round_price_to_increments() {
+        assert_eq!(round_price(dec!(12.33), RoundingStrategy::HalfUp, dec!(0.05), dec!(0)), Some(dec!(12.35)));
+        assert_eq!(round_price(dec!(12.32), RoundingStrategy::HalfUp, dec!(0.05), dec!(0)), Some(dec!(12.3)));
+        assert_eq!(round_price(dec!(12.33), RoundingStrategy::Floor, dec!(0.1), dec!(0)), Some(dec!(12.3)));
+        assert_eq!(round_price(dec!(12.01), RoundingStrategy::Ceil, dec!(0.25), dec!(0)), Some(dec!(12.25)));
+        assert_eq!(round_price(dec!(47), RoundingStrategy::HalfUp, dec!(5), dec!(0)), Some(dec!(45)));
+        assert_eq!(round_price(dec!(47.5), RoundingStrategy::HalfUp, dec!(5), dec!(0)), Some(dec!(50)));
+        assert_eq!(round_price(dec!(47), RoundingStrategy::Ceil, dec!(10), dec!(0)), Some(dec!(50)));
+    }
+
+    #[test]
+    fn test_truncate_moves_towards_zero_below_charm_ending() {
+        assert_eq!(round_price(dec!(0.50), RoundingStrategy::Truncate, dec!(0.05), dec!(0.99)), Some(dec!(0.49)));
+        assert_eq!(round_price(dec!(23.47), RoundingStrategy::Truncate, dec!(1), dec!(0.99)), Some(dec!(22.99)));
+        assert_eq!(round_price(dec!(-1.25), RoundingStrategy::Truncate, dec!(1), dec!(0)), Some(dec!(-1)));
+    }
+
+    #[test]
+    fn test_round_price_overflow_is_none() {
+        assert_eq!(round_price(Decimal::MAX, RoundingStrategy::Ceil, dec!(10), dec!(0)), None);
+    }
+
+    #[test]
+    fn test_grid_step_follows_ending() {
+        let rule = |ending| RoundingRule { ending: Some(ending), ..RoundingRule::default() };
+
+        assert_eq!(rule(dec!(0.99)).grid(), Some((dec!(1), dec!(0.99))));
+        assert_eq!(rule(dec!(0)).grid(), Some((dec!(1), dec!(0))));
+        assert_eq!(rule(dec!(9)).grid(), Some((dec!(10), dec!(9))));
+        assert_eq!(rule(dec!(49)).grid(), Some((dec!(100), dec!(49))));
+        assert_eq!(rule(Decimal::MAX).grid(), None);
+    }
+
+    #[test]
+    fn test_grid_step_follows_increment() {
+        let rule = |increment, ending| RoundingRule {
+            increment: Some(increment),
+            ending,
+            ..RoundingRule::default()
+        };
+
+        assert_eq!(rule(dec!(0.05), None).grid(), Some((dec!(0.05), dec!(0))));
+        assert_eq!(rule(dec!(5), Some(dec!(4.99))).grid(), Some((dec!(5), dec!(4.99))));
+        assert_eq!(rule(dec!(0), None).grid(), Some((dec!(1), dec!(0))));
+        assert_eq!(rule(dec!(-5), None).grid(), Some((dec!(1), dec!(0))));
+    }
+
+    #[test]
+    fn test_grid_step_follows_decimal_places() {
+        let rule = |decimal_places| RoundingRule { decimal_places, ..RoundingRule::default() };
+
+        assert_eq!(rule(0).grid(), Some((dec!(1), dec!(0))));
+        assert_eq!(rule(2).grid(), Some((dec!(0.01), dec!(0))));
+        assert_eq!(rule(99).grid(), Some((Decimal::new(1, MAX_DECIMAL_PLACES), dec!(0))));
+    }
+
+    #[test]
+    fn test_round_line_rounds_unit_price_by_default() {
+        let rule = RoundingRule::default();
+
+        assert_eq!(
+            rule.round_line(dec!(0.37), 40, "USD"),
+            Some(LineRounding { original: dec!(0.37), rounded: dec!(0), unit_price: dec!(0) })
+        );
+    }
+
+    #[test]
+    fn test_round_line_splits_rounded_total_evenly() {
+        let rule = RoundingRule { target: RoundingTarget::LineTotal, ..RoundingRule::default() };
+
+        assert_eq!(
+            rule.round_line(dec!(0.37), 40, "USD"),
+            Some(LineRounding { original: dec!(14.80), rounded: dec!(14), unit_price: dec!(0.35) })
+        );
+    }
+
+    #[test]
+    fn test_round_line_splits_uneven_total_towards_original() {
+        let rule = RoundingRule { target: RoundingTarget::LineTotal, ..RoundingRule::default() };
+
+        // 3 x 3.45 = 10.35 -> 10.00, which is 3.333... per unit
+        assert_eq!(
+            rule.round_line(dec!(3.45), 3, "USD"),
+            Some(LineRounding { original: dec!(10.35), rounded: dec!(10.02), unit_price: dec!(3.34) })
+        );
+
+        let rule = RoundingRule { strategy: RoundingStrategy::Ceil, ..rule };
+        assert_eq!(
+            rule.round_line(dec!(3.45), 3, "USD"),
+            Some(LineRounding { original: dec!(10.35), rounded: dec!(10.98), unit_price: dec!(3.66) })
+        );
+
+        let rule = RoundingRule { unit_decimal_places: Some(0), increment: Some(dec!(100)), ..rule };
+        assert_eq!(
+            rule.round_line(dec!(345), 3, "USD"),
+            Some(LineRounding { original: dec!(1035), rounded: dec!(1098), unit_price: dec!(366) })
+        );
+    }
+
+    #[test]
+    fn test_round_line_splits_totals_into_the_currency_minor_units() {
+        let rule = RoundingRule { target: RoundingTarget::LineTotal, increment: Some(dec!(10)), ..RoundingRule::default() };
+
+        // 3 x 1234 = 3702 -> 3700, which is 1233.33... per unit, kept in whole yen
+        assert_eq!(
+            rule.round_line(dec!(1234), 3, "JPY"),
+            Some(LineRounding { original: dec!(3702), rounded: dec!(3702), unit_price: dec!(1234) })
+        );
+        assert_eq!(
+            rule.round_line(dec!(1234), 4, "JPY"),
+            Some(LineRounding { original: dec!(4936), rounded: dec!(4932), unit_price: dec!(1233) })
+        );
+        assert_eq!(rule.unit_decimal_places("KWD"), 3);
+        assert_eq!(RoundingRule { unit_decimal_places: Some(2), ..rule }.unit_decimal_places("JPY"), 2);
+    }
+
+    #[test]
+    fn test_round_line_needs_positive_quantity_for_line_totals() {
+        let rule = RoundingRule { target: RoundingTarget::LineTotal, ..RoundingRule::default() };
+
+        assert_eq!(rule.round_line(dec!(0.37), 0, "USD"), None);
+        assert_eq!(rule.round_line(dec!(0.37), -1, "USD"), None);
+    }
+
+    #[test]
+    fn test_round_line_above_keeps_floor() {
+        let rule = RoundingRule { ending: Some(dec!(0.99)), ..RoundingRule::default() };
+
+        assert_eq!(rule.round_line_above(dec!(23.40), 1, "USD", dec!(20)).map(|rounding| rounding.unit_price), Some(dec!(22.99)));
+        assert_eq!(rule.round_line_above(dec!(23.40), 1, "USD", dec!(23.10)).map(|rounding| rounding.unit_price), Some(dec!(23.99)));
+        assert_eq!(rule.round_line_above(dec!(23.40), 1, "USD", dec!(24.50)), None);
+    }
+
+    #[test]
+    fn test_guardrail_blocks_increases_unless_allowed() {
+        let guardrail = Guardrail::default();
+        assert!(guardrail.allows(dec!(23.40), dec!(23)));
+        assert!(!guardrail.allows(dec!(23.40), dec!(24)));
+
+        let guardrail = Guardrail { allow_price_increase: true, ..Guardrail::default() };
+        assert!(guardrail.allows(dec!(23.40), dec!(24)));
+    }
+
+    #[test]
+    fn test_guardrail_limits_absolute_change() {
+        let guardrail = Guardrail { max_price_change: Some(dec!(0.50)), ..Guardrail::default() };
+
+        assert!(guardrail.allows(dec!(23.40), dec!(23)));
+        assert!(guardrail.allows(dec!(23.50), dec!(23)));
+        assert!(!guardrail.allows(dec!(23.51), dec!(23)));
+    }
+
+    #[test]
+    fn test_guardrail_limits_percent_change() {
+        let guardrail = Guardrail { max_price_change_percent: Some(dec!(2)), ..Guardrail::default() };
+
+        assert!(guardrail.allows(dec!(100.00), dec!(98)));
+        assert!(!guardrail.allows(dec!(100.00), dec!(97.99)));
+        assert!(!guardrail.allows(dec!(0.40), dec!(0)));
+        assert!(guardrail.allows(dec!(0), dec!(0)));
+    }
+
+    #[test]
+    fn test_guardrail_capped_by_keeps_the_lower_limits() {
+        let ceiling = Guardrail {
+            max_price_change: Some(dec!(1)),
+            max_price_change_percent: Some(dec!(5)),
+            allow_price_increase: false,
+        };
+
+        assert_eq!(Guardrail::default().capped_by(&ceiling), ceiling);
+
+        let rule = Guardrail {
+            max_price_change: Some(dec!(0.50)),
+            max_price_change_percent: Some(dec!(10)),
+            allow_price_increase: true,
+        };
+        assert_eq!(
+            rule.capped_by(&ceiling),
+            Guardrail {
+                max_price_change: Some(dec!(0.50)),
+                max_price_change_percent: Some(dec!(5)),
+                allow_price_increase: true,
+            }
+        );
+    }
+
+    fn rounded(cents: u32, strategy: u8, step: u8) -> (Decimal, Decimal, RoundingStrategy, Decimal) {
+        let price = Decimal::new(cents as i64, 2);
+        let strategy = STRATEGIES[strategy as usize % STRATEGIES.len()];
+        let step = STEPS[step as usize % STEPS.len()];
+        let result = round_price(price, strategy, step, Decimal::ZERO).unwrap();
+        (price, result, strategy, step)
+    }
+
+    quickcheck! {
+        fn prop_never_rounds_in_the_wrong_direction(cents: u32, strategy: u8, step: u8) -> bool {
+            let (price, result, strategy, _) = rounded(cents, strategy, step);
+            match strategy {
+                RoundingStrategy::Floor | RoundingStrategy::Truncate => result <= price,
+                RoundingStrategy::Ceil => result >= price,
+                RoundingStrategy::HalfUp | RoundingStrategy::HalfEven => true,
+            }
+        }
+
+        fn prop_never_moves_by_a_full_increment(cents: u32, strategy: u8, step: u8) -> bool {
+            let (price, result, strategy, step) = rounded(cents, strategy, step);
+            let distance = (result - price).abs();
+            match strategy {
+                RoundingStrategy::HalfUp | RoundingStrategy::HalfEven => distance * dec!(2) <= step,
+                _ => distance < step,
+            }
+        }
+
+        fn prop_result_is_on_the_grid(cents: u32, strategy: u8, step: u8) -> bool {
+            let (_, result, _, step) = rounded(cents, strategy, step);
+            (result % step).is_zero()
+        }
+
+        fn prop_charm_ending_stays_within_one_step(cents: u32, strategy: u8) -> TestResult {
+            let price = Decimal::new(cents as i64, 2);
+            let strategy = STRATEGIES[strategy as usize % STRATEGIES.len()];
+            let result = round_price(price, strategy, dec!(1), dec!(0.99)).unwrap();
+            if result.is_sign_negative() {
+                return TestResult::discard();
+            }
+            TestResult::from_bool((result - price).abs() < dec!(1) && result.fract() == dec!(0.99))
+        }
+    }
+}
@@ -1,4 +1,8 @@
 use run::input::InputCart as Cart;
+use run::input::InputCartBuyerIdentity as BuyerIdentity;
+use run::input::InputCartLines as CartLine;
+use run::input::InputCartLinesMerchandise as CartLineMerchandise;
+use run::input::InputLocalization as Localization;
 use run::output::CartOperation;
 use run::output::UpdateOperation;
 use run::output::UpdateOperationPriceAdjustment;
@@ -7,26 +11,294 @@ use run::output::UpdateOperationFixedPricePerUnitAdjustment;
 use shopify_function::prelude::*;
 use shopify_function::Result;
 
+use crate::rounding::{Guardrail, RoundingRule};
+use rust_decimal::prelude::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Key in `sellingPlans` matching every selling plan.
+const ANY_SELLING_PLAN: &str = "any";
+
+/// Title Shopify gives the only variant of a product without options.
+const DEFAULT_VARIANT_TITLE: &str = "Default Title";
+
+/// Settings stored as JSON in the CartTransform `function-configuration` metafield.
+/// The rule for a line is taken from `sellingPlans`, then `countries`, then
+/// `markets`, then `currencies`, falling back to the top-level rule. An absent metafield keeps
+/// the original behaviour of flooring to whole units; one that can't be parsed leaves
+/// every price unchanged, since its scope rules and exemptions can't be trusted.
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all(deserialize = "camelCase"), default)]
+struct Configuration {
+    #[serde(flatten)]
+    default_rule: RoundingRule,
+    /// Rules keyed by ISO currency code, e.g. `JPY` or `CHF`.
+    currencies: HashMap<String, RoundingRule>,
+    /// Rules keyed by market handle or market ID.
+    markets: HashMap<String, RoundingRule>,
+    /// Rules keyed by ISO country code, e.g. `US` or `DE`.
+    countries: HashMap<String, RoundingRule>,
+    /// Rules for subscription lines keyed by selling plan ID, or by `any` for
+    /// every selling plan without its own entry.
+    selling_plans: HashMap<String, RoundingRule>,
+    #[serde(flatten)]
+    scope: Scope,
+    #[serde(flatten)]
+    exemptions: Exemptions,
+    /// Appended to the title of every rounded line, e.g. `(rounded from {original})`.
+    /// `{original}` and `{rounded}` are replaced with the old and new unit prices.
+    title_suffix: Option<String>,
+    /// ISO code of the shop's currency, e.g. `USD`. Function input doesn't carry
+    /// it, and `money` cost and MAP metafields are only used when they match it.
+    shop_currency: Option<String>,
+}
+
+/// Buyers who always see unrounded prices, such as wholesale customers on
+/// contract pricing. `exemptCustomerTags` is also an input query variable.
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all(deserialize = "camelCase"), default)]
+struct Exemptions {
+    exempt_customer_tags: Vec<String>,
+    /// Exempt every buyer purchasing on behalf of a B2B company.
+    exempt_companies: bool,
+    exempt_company_ids: Vec<String>,
+    exempt_company_location_ids: Vec<String>,
+}
+
+impl Exemptions {
+    const FIELDS: &'static [&'static str] =
+        &["exemptCustomerTags", "exemptCompanies", "exemptCompanyIds", "exemptCompanyLocationIds"];
+
+    fn exempts(&self, buyer_identity: Option<&BuyerIdentity>) -> bool {
+        let buyer_identity = match buyer_identity {
+            Some(buyer_identity) => buyer_identity,
+            None => return false,
+        };
+
+        let exempt_by_tag = buyer_identity
+            .customer
+            .as_ref()
+            .map_or(false, |customer| customer.exempt_by_tag);
+        let exempt_by_company = buyer_identity.purchasing_company.as_ref().map_or(false, |purchasing_company| {
+            self.exempt_companies
+                || self.exempt_company_ids.contains(&purchasing_company.company.id)
+                || self.exempt_company_location_ids.contains(&purchasing_company.location.id)
+        });
+
+        exempt_by_tag || exempt_by_company
+    }
+}
+
+/// Which cart lines get rounded. With no include rules every line is in scope;
+/// otherwise a line must match at least one of them. A line matching any exclude
+/// rule is never rounded.
+///
+/// The tag and collection lists double as input query variables, so Shopify
+/// resolves `hasAnyTag`/`inAnyCollection` for them before the function runs.
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all(deserialize = "camelCase"), default)]
+struct Scope {
+    include_tags: Vec<String>,
+    exclude_tags: Vec<String>,
+    include_collection_ids: Vec<String>,
+    exclude_collection_ids: Vec<String>,
+    include_product_types: Vec<String>,
+    exclude_product_types: Vec<String>,
+    include_variant_ids: Vec<String>,
+    exclude_variant_ids: Vec<String>,
+    exclude_gift_cards: bool,
+}
+
+impl Scope {
+    const FIELDS: &'static [&'static str] = &[
+        "includeTags",
+        "excludeTags",
+        "includeCollectionIds",
+        "excludeCollectionIds",
+        "includeProductTypes",
+        "excludeProductTypes",
+        "includeVariantIds",
+        "excludeVariantIds",
+        "excludeGiftCards",
+    ];
+
+    fn has_include_rules(&self) -> bool {
+        !self.include_tags.is_empty()
+            || !self.include_collection_ids.is_empty()
+            || !self.include_product_types.is_empty()
+            || !self.include_variant_ids.is_empty()
+    }
+
+    fn includes(&self, merchandise: &CartLineMerchandise) -> bool {
+        match merchandise {
+            CartLineMerchandise::ProductVariant(variant) => {
+                let product = &variant.product;
+                let product_type = product.product_type.as_deref().unwrap_or_default();
+
+                let excluded = product.excluded_by_tag
+                    || product.excluded_by_collection
+                    || (self.exclude_gift_cards && product.is_gift_card)
+                    || self.exclude_product_types.iter().any(|excluded| excluded == product_type)
+                    || self.exclude_variant_ids.contains(&variant.id);
+                let included = !self.has_include_rules()
+                    || product.included_by_tag
+                    || product.included_by_collection
+                    || self.include_product_types.iter().any(|included| included == product_type)
+                    || self.include_variant_ids.contains(&variant.id);
+
+                included && !excluded
+            }
+            // Custom products carry no tags, collections or variant ID to match against
+            CartLineMerchandise::CustomProduct(custom_product) => {
+                let excluded = self.exclude_gift_cards && custom_product.is_gift_card;
+                !self.has_include_rules() && !excluded
+            }
+        }
+    }
+}
+
+impl Configuration {
+    /// Keys of the rule maps, each holding rules keyed by currency, market,
+    /// country or selling plan.
+    const RULE_MAPS: &'static [&'static str] = &["currencies", "markets", "countries", "sellingPlans"];
+
+    /// Parses the metafield, rejecting keys no setting uses. Serde can't deny
+    /// unknown fields next to `flatten`, so they're checked against each
+    /// struct's `FIELDS` before deserializing.
+    fn from_str(value: &str) -> serde_json::Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(value)?;
+        let rule_fields = [RoundingRule::FIELDS, Guardrail::FIELDS];
+
+        check_fields(
+            &value,
+            "",
+            &[RoundingRule::FIELDS, Guardrail::FIELDS, Self::RULE_MAPS, &["titleSuffix", "shopCurrency"], Scope::FIELDS, Exemptions::FIELDS],
+        )?;
+        for map in Self::RULE_MAPS {
+            if let Some(serde_json::Value::Object(rules)) = value.get(map) {
+                for (key, rule) in rules {
+                    check_fields(rule, &format!("{}.{}.", map, key), &rule_fields)?;
+                }
+            }
+        }
+
+        serde_json::from_value(value)
+    }
+
+    fn rule_for(&self, localization: &Localization, line: &CartLine) -> &RoundingRule {
+        let selling_plan_rule = line.selling_plan_allocation.as_ref().and_then(|allocation| {
+            self.selling_plans
+                .get(&allocation.selling_plan.id)
+                .or_else(|| self.selling_plans.get(ANY_SELLING_PLAN))
+        });
+
+        selling_plan_rule
+            .or_else(|| self.countries.get(&localization.country.iso_code))
+            .or_else(|| self.markets.get(&localization.market.handle))
+            .or_else(|| self.markets.get(&localization.market.id))
+            .or_else(|| self.currencies.get(&line.cost.amount_per_quantity.currency_code))
+            .unwrap_or(&self.default_rule)
+    }
+}
+
 #[shopify_function_target(query_path = "src/run.graphql", schema_path = "schema.graphql")]
 fn run(input: input::ResponseData) -> Result<output::FunctionRunResult> {
-    let cart_operations: Vec<CartOperation> = get_update_cart_operations(&input.cart);
+    let config = match input.cart_transform.metafield {
+        Some(input::InputCartTransformMetafield { value }) => match Configuration::from_str(&value) {
+            Ok(config) => config,
+            Err(error) => {
+                eprintln!("Unable to parse configuration value from metafield, leaving prices unchanged: {}", error);
+                return Ok(output::FunctionRunResult { operations: vec![] });
+            }
+        },
+        None => Configuration::default(),
+    };
+
+    if config.exemptions.exempts(input.cart.buyer_identity.as_ref()) {
+        eprintln!("Buyer is exempt from rounding");
+        return Ok(output::FunctionRunResult { operations: vec![] });
+    }
+
+    let cart_operations: Vec<CartOperation> =
+        get_update_cart_operations(&input.cart, &input.localization, input.presentment_currency_rate, &config);
 
     Ok(output::FunctionRunResult {
         operations: cart_operations,
     })
 }
 
-fn get_update_cart_operations(cart: &Cart) -> Vec<CartOperation> {
+fn get_update_cart_operations(
+    cart: &Cart,
+    localization: &Localization,
+    presentment_currency_rate: Decimal,
+    config: &Configuration,
+) -> Vec<CartOperation> {
     let mut result: Vec<CartOperation> = Vec::new();
 
     for line in cart.lines.iter() {
-        // Check if the price has a decimal part
-        if line.cost.amount_per_quantity.amount.fract() != 0.0 {
-            let rounded_price = line.cost.amount_per_quantity.amount.floor();
+        if !config.scope.includes(&line.merchandise) {
+            continue;
+        }
+
+        let rule = config.rule_for(localization, line);
+        if rule.skip {
+            continue;
+        }
+
+        let price = match exact_amount(&line.cost.amount_per_quantity.amount) {
+            Some(price) => price,
+            None => {
+                eprintln!(
+                    "Skipping line {}: price {} can't be represented as a decimal",
+                    line.id,
+                    line.cost.amount_per_quantity.amount.as_f64()
+                );
+                continue;
+            }
+        };
+        let currency_code = &line.cost.amount_per_quantity.currency_code;
+        let rounding = match price_floor(&line.merchandise, presentment_currency_rate, config.shop_currency.as_deref()) {
+            Some(floor) => rule.round_line_above(price, line.quantity, currency_code, floor),
+            None => rule.round_line(price, line.quantity, currency_code),
+        };
+        let rounding = match rounding {
+            Some(rounding) => rounding,
+            None => {
+                eprintln!("Unable to round price {} on line {}", price, line.id);
+                continue;
+            }
+        };
+
+        // Prices below the first charm ending would otherwise go negative
+        if rounding.rounded.is_sign_negative() {
+            continue;
+        }
+
+        // The top-level limits cover every rule, so a misconfigured market or
+        // currency rule can't move prices further than they allow
+        let guardrail = rule.guardrail.capped_by(&config.default_rule.guardrail);
+        if !guardrail.allows(rounding.original, rounding.rounded) {
+            eprintln!(
+                "Skipping line {}: rounding {} to {} is outside the guardrail",
+                line.id, rounding.original, rounding.rounded
+            );
+            continue;
+        }
+
+        // Only touch lines that are not already on the rounding grid
+        if rounding.unit_price != price {
+            let amount = match rounding.unit_price.to_f64() {
+                Some(amount) => amount,
+                None => {
+                    eprintln!("Skipping line {}: rounded price {} can't be sent back", line.id, rounding.unit_price);
+                    continue;
+                }
+            };
 
             let price_adjustment_value = UpdateOperationPriceAdjustmentValue::FixedPricePerUnit(
                 UpdateOperationFixedPricePerUnitAdjustment {
-                    amount: Decimal(rounded_price),
+                    amount: Decimal(amount),
                 }
             );
 
@@ -38,12 +310,1037 @@ fn get_update_cart_operations(cart: &Cart) -> Vec<CartOperation> {
                 cart_line_id: line.id.clone(),
                 price: Some(price_adjustment),
                 image: None,
-                title: None,
+                title: config.title_suffix.as_ref().map(|suffix| {
+                    let decimal_places = rule.unit_decimal_places(currency_code) as usize;
+                    let suffix = suffix
+                        .replace("{original}", &format!("{:.*}", decimal_places, price))
+                        .replace("{rounded}", &format!("{:.*}", decimal_places, rounding.unit_price));
+                    format!("{} {}", merchandise_title(&line.merchandise), suffix)
+                }),
             };
 
             result.push(CartOperation::Update(update_operation));
         }
     }
 
-    return result;
-}
\ No newline at end of file
+    result
+}
+
+/// The line title the way Shopify composes it, e.g. "Snowboard - Large", so
+/// variants of the same product can still be told apart.
+/// Fails on the first key of `value` missing from every list in `fields`,
+/// naming it with `prefix`, e.g. `currencies.JPY.incremnt`.
+fn check_fields(value: &serde_json::Value, prefix: &str, fields: &[&[&str]]) -> serde_json::Result<()> {
+    use serde::de::Error;
+
+    match value
+        .as_object()
+        .and_then(|object| object.keys().find(|key| !fields.iter().any(|fields| fields.contains(&key.as_str()))))
+    {
+        Some(key) => Err(serde_json::Error::custom(format!("unknown field `{}{}`", prefix, key))),
+        None => Ok(()),
+    }
+}
+
+fn merchandise_title(merchandise: &CartLineMerchandise) -> String {
+    match merchandise {
+        CartLineMerchandise::ProductVariant(variant) => match variant.title.as_deref() {
+            Some(title) if !title.is_empty() && title != DEFAULT_VARIANT_TITLE => {
+                format!("{} - {}", variant.product.title, title)
+            }
+            _ => variant.product.title.clone(),
+        },
+        CartLineMerchandise::CustomProduct(custom_product) => custom_product.title.clone(),
+    }
+}
+
+/// The lowest unit price a variant may be rounded to: the higher of its `custom.cost`
+/// and `custom.minimum_advertised_price` metafields, converted from the shop's
+/// currency into the presentment currency.
+fn price_floor(
+    merchandise: &CartLineMerchandise,
+    presentment_currency_rate: Decimal,
+    shop_currency: Option<&str>,
+) -> Option<rust_decimal::Decimal> {
+    let variant = match merchandise {
+        CartLineMerchandise::ProductVariant(variant) => variant,
+        CartLineMerchandise::CustomProduct(_) => return None,
+    };
+
+    let cost = variant.cost.as_ref().and_then(|metafield| metafield_amount(&metafield.value, shop_currency));
+    let minimum_advertised_price = variant
+        .minimum_advertised_price
+        .as_ref()
+        .and_then(|metafield| metafield_amount(&metafield.value, shop_currency));
+    let floor = cost.into_iter().chain(minimum_advertised_price).max()?;
+
+    floor.checked_mul(exact_amount(&presentment_currency_rate)?)
+}
+
+/// Reads a `number_decimal` metafield (`"12.50"`) or a `money` metafield
+/// (`{"amount": "12.50", "currency_code": "USD"}`). Money in another currency
+/// than `shop_currency` would be converted with the wrong rate, so it's skipped.
+fn metafield_amount(value: &str, shop_currency: Option<&str>) -> Option<rust_decimal::Decimal> {
+    #[derive(Deserialize)]
+    struct Money {
+        amount: rust_decimal::Decimal,
+        currency_code: String,
+    }
+
+    if let Ok(amount) = rust_decimal::Decimal::from_str(value.trim()) {
+        return Some(amount);
+    }
+
+    let money = serde_json::from_str::<Money>(value).ok()?;
+    match shop_currency {
+        Some(shop_currency) if shop_currency.eq_ignore_ascii_case(&money.currency_code) => Some(money.amount),
+        Some(shop_currency) => {
+            eprintln!(
+                "Ignoring price floor of {} {}: not in the shop currency {}",
+                money.amount, money.currency_code, shop_currency
+            );
+            None
+        }
+        None => {
+            eprintln!(
+                "Ignoring price floor of {} {}: set shopCurrency to use money metafields",
+                money.amount, money.currency_code
+            );
+            None
+        }
+    }
+}
+
+/// Shopify's `Decimal` scalar arrives as an `f64`. Its shortest string form gives
+/// back the digits Shopify sent (e.g. `19.999999`) without binary float artifacts,
+/// as long as they fit in the 15 to 17 significant digits an `f64` holds. Longer
+/// amounts are already rounded by then, e.g. `99999999999999999999.5` reads as
+/// `1e20`. Returns `None` for amounts too large for a decimal, such as `1e30`.
+fn exact_amount(amount: &Decimal) -> Option<rust_decimal::Decimal> {
+    rust_decimal::Decimal::from_str(&amount.as_f64().to_string()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shopify_function::{run_function_with_input, Result};
+    use std::{env, fs, path::Path};
+
+    /// Function input for a cart in the US market with an anonymous buyer,
+    /// changed with the setters below and rendered with `json`.
+    struct CartInput {
+        lines: Vec<serde_json::Value>,
+        country_code: String,
+        market_handle: String,
+        buyer_identity: serde_json::Value,
+        metafield: String,
+    }
+
+    impl CartInput {
+        fn new(metafield: &str) -> Self {
+            CartInput {
+                lines: vec![],
+                country_code: "US".to_string(),
+                market_handle: "us".to_string(),
+                buyer_identity: serde_json::Value::Null,
+                metafield: metafield.to_string(),
+            }
+        }
+
+        /// Adds a line of one snowboard at `amount`.
+        fn line(mut self, amount: &str, currency_code: &str) -> Self {
+            self.lines.push(cart_line(amount, currency_code));
+            self
+        }
+
+        fn lines(mut self, lines: Vec<serde_json::Value>) -> Self {
+            self.lines.extend(lines);
+            self
+        }
+
+        fn country(mut self, country_code: &str, market_handle: &str) -> Self {
+            self.country_code = country_code.to_string();
+            self.market_handle = market_handle.to_string();
+            self
+        }
+
+        fn buyer(mut self, buyer_identity: serde_json::Value) -> Self {
+            self.buyer_identity = buyer_identity;
+            self
+        }
+
+        fn json(&self) -> String {
+            let localization = serde_json::json!({
+                "country": { "isoCode": self.country_code },
+                "market": { "id": "gid://shopify/Market/1", "handle": self.market_handle }
+            });
+
+            format!(
+                r#"{{ "cart": {{ "lines": {}, "buyerIdentity": {} }}, "presentmentCurrencyRate": "1.0", "localization": {}, "cartTransform": {{ "metafield": {} }} }}"#,
+                serde_json::Value::Array(self.lines.clone()),
+                self.buyer_identity,
+                localization,
+                self.metafield
+            )
+        }
+    }
+
+    fn cart_line(amount: &str, currency_code: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": "gid://shopify/CartLine/1",
+            "quantity": 1,
+            "cost": {
+                "amountPerQuantity": {
+                    "amount": amount,
+                    "currencyCode": currency_code
+                }
+            },
+            "merchandise": {
+                "__typename": "ProductVariant",
+                "id": "gid://shopify/ProductVariant/1",
+                "product": {
+                    "title": "Snowboard",
+                    "productType": "Snowboard",
+                    "isGiftCard": false,
+                    "includedByTag": false,
+                    "excludedByTag": false,
+                    "includedByCollection": false,
+                    "excludedByCollection": false
+                }
+            }
+        })
+    }
+
+    fn fixed_price_update(amount: f64) -> run::output::CartOperation {
+        use run::output::*;
+
+        CartOperation::Update(UpdateOperation {
+            cart_line_id: "gid://shopify/CartLine/1".to_string(),
+            price: Some(UpdateOperationPriceAdjustment {
+                adjustment: UpdateOperationPriceAdjustmentValue::FixedPricePerUnit(
+                    UpdateOperationFixedPricePerUnitAdjustment {
+                        amount: Decimal(amount),
+                    },
+                ),
+            }),
+            image: None,
+            title: None,
+        })
+    }
+
+    #[test]
+    fn test_floors_price_when_metafield_is_missing() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(run, &CartInput::new("null").line("629.44", "USD").json())?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(629.0)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_whole_price_is_left_untouched() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(run, &CartInput::new("null").line("629.00", "USD").json())?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_already_on_decimal_grid_is_left_untouched() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"decimalPlaces\": 1}" }"#).line("0.3", "USD").json(),
+        )?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ceil_strategy_from_metafield() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"strategy\": \"ceil\", \"allowPriceIncrease\": true}" }"#)
+                .line("23.01", "USD")
+                .json(),
+        )?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(24.0)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_metafield_leaves_prices_unchanged() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"strategy\": \"sideways\"}" }"#).line("23.75", "USD").json(),
+        )?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_misspelt_keys_leave_prices_unchanged() -> Result<()> {
+        use run::output::*;
+
+        for metafield in [
+            r#"{ "value": "{\"stratgy\": \"up\"}" }"#,
+            r#"{ "value": "{\"excludeTag\": [\"no-rounding\"]}" }"#,
+            r#"{ "value": "{\"currencies\": {\"JPY\": {\"increment\": 10, \"maxDecrease\": 5}}}" }"#,
+            r#"{ "value": "{\"sellingPlans\": {\"any\": {\"titleSuffix\": \"(rounded)\"}}}" }"#,
+        ] {
+            let result = run_function_with_input(run, &CartInput::new(metafield).line("23.75", "USD").json())?;
+
+            assert_eq!(result, FunctionRunResult { operations: vec![] }, "{}", metafield);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_fields_are_named_with_their_path() {
+        let error = |value: &str| Configuration::from_str(value).err().map(|error| error.to_string());
+
+        assert_eq!(error(r#"{"stratgy": "up"}"#), Some("unknown field `stratgy`".to_string()));
+        assert_eq!(
+            error(r#"{"currencies": {"JPY": {"maxDecrease": 5}}}"#),
+            Some("unknown field `currencies.JPY.maxDecrease`".to_string())
+        );
+        assert_eq!(error(r#"{"strategy": "up", "maxPriceChange": 1, "excludeTags": ["sale"], "exemptCompanies": true}"#), None);
+    }
+
+    #[test]
+    fn test_increment_from_metafield() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"increment\": 0.05, \"direction\": \"nearest\", \"allowPriceIncrease\": true}" }"#)
+                .line("12.33", "USD")
+                .json(),
+        )?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(12.35)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_charm_ending_direction_from_metafield() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"ending\": 0.99, \"direction\": \"up\", \"allowPriceIncrease\": true}" }"#)
+                .line("23.40", "USD")
+                .json(),
+        )?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(23.99)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_below_first_ending_is_left_untouched() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"ending\": 0.99}" }"#).line("0.50", "USD").json(),
+        )?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    const CURRENCY_RULES: &str = r#"{ "value": "{\"ending\": 0.99, \"currencies\": {\"JPY\": {\"increment\": 10, \"direction\": \"nearest\"}, \"CHF\": {\"increment\": 0.05, \"direction\": \"nearest\", \"allowPriceIncrease\": true}}}" }"#;
+
+    #[test]
+    fn test_currency_rule_applies_to_matching_presentment_currency() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(run, &CartInput::new(CURRENCY_RULES).line("1234", "JPY").json())?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(1230.0)],
+        };
+        assert_eq!(result, expected);
+
+        let result = run_function_with_input(run, &CartInput::new(CURRENCY_RULES).line("12.33", "CHF").json())?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(12.35)],
+        };
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unlisted_currency_uses_default_rule() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(run, &CartInput::new(CURRENCY_RULES).line("23.40", "USD").json())?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(22.99)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_increase_is_skipped_unless_allowed() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"strategy\": \"ceil\"}" }"#).line("23.01", "USD").json(),
+        )?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_beyond_guardrail_is_skipped() -> Result<()> {
+        use run::output::*;
+
+        let metafield = r#"{ "value": "{\"increment\": 10, \"maxPriceChange\": 5, \"maxPriceChangePercent\": 20}" }"#;
+
+        let result = run_function_with_input(run, &CartInput::new(metafield).line("23.40", "USD").json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(20.0)] });
+
+        let result = run_function_with_input(run, &CartInput::new(metafield).line("29.40", "USD").json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        let result = run_function_with_input(run, &CartInput::new(metafield).line("14.40", "USD").json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_total_mode_derives_unit_price() -> Result<()> {
+        use run::output::*;
+
+        let mut line = cart_line("0.37", "USD");
+        line["quantity"] = serde_json::json!(40);
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"target\": \"lineTotal\"}" }"#).lines(vec![line]).json(),
+        )?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(0.35)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_excluded_tag_is_left_untouched() -> Result<()> {
+        use run::output::*;
+
+        let mut line = cart_line("23.40", "USD");
+        line["merchandise"]["product"]["excludedByTag"] = serde_json::json!(true);
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"excludeTags\": [\"clearance\"]}" }"#).lines(vec![line]).json(),
+        )?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_rules_limit_rounding_to_matching_lines() -> Result<()> {
+        use run::output::*;
+
+        let metafield = r#"{ "value": "{\"includeCollectionIds\": [\"gid://shopify/Collection/1\"], \"includeProductTypes\": [\"Wax\"]}" }"#;
+
+        let result = run_function_with_input(run, &CartInput::new(metafield).line("23.40", "USD").json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        let mut line = cart_line("23.40", "USD");
+        line["merchandise"]["product"]["includedByCollection"] = serde_json::json!(true);
+        let result = run_function_with_input(run, &CartInput::new(metafield).lines(vec![line]).json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(23.0)] });
+
+        let mut line = cart_line("23.40", "USD");
+        line["merchandise"]["product"]["productType"] = serde_json::json!("Wax");
+        let result = run_function_with_input(run, &CartInput::new(metafield).lines(vec![line]).json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(23.0)] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_rules_win_over_include_rules() -> Result<()> {
+        use run::output::*;
+
+        let mut line = cart_line("23.40", "USD");
+        line["merchandise"]["product"]["includedByTag"] = serde_json::json!(true);
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"includeTags\": [\"sale\"], \"excludeVariantIds\": [\"gid://shopify/ProductVariant/1\"]}" }"#)
+                .lines(vec![line])
+                .json(),
+        )?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gift_cards_can_be_excluded() -> Result<()> {
+        use run::output::*;
+
+        let custom_gift_card = serde_json::json!({
+            "id": "gid://shopify/CartLine/1",
+            "quantity": 1,
+            "cost": { "amountPerQuantity": { "amount": "23.40", "currencyCode": "USD" } },
+            "merchandise": { "__typename": "CustomProduct", "title": "Gift card", "isGiftCard": true }
+        });
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"excludeGiftCards\": true}" }"#)
+                .lines(vec![custom_gift_card.clone()])
+                .json(),
+        )?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new("null").lines(vec![custom_gift_card]).json(),
+        )?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(23.0)] });
+        Ok(())
+    }
+
+    const MARKET_RULES: &str = r#"{ "value": "{\"ending\": 0.99, \"markets\": {\"eu\": {}}, \"countries\": {\"CH\": {\"increment\": 0.05}}, \"currencies\": {\"EUR\": {\"ending\": 0.95}}}" }"#;
+
+    #[test]
+    fn test_market_rule_applies_to_matching_market() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(MARKET_RULES).line("23.40", "EUR").country("DE", "eu").json(),
+        )?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(23.0)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_country_rule_wins_over_market_rule() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(MARKET_RULES).line("12.33", "CHF").country("CH", "eu").json(),
+        )?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(12.3)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unlisted_market_falls_back_to_currency_then_default_rule() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(MARKET_RULES).line("23.40", "EUR").country("IE", "ie").json(),
+        )?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(22.95)] });
+
+        let result = run_function_with_input(run, &CartInput::new(MARKET_RULES).line("23.40", "USD").json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(22.99)] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_level_guardrail_caps_every_rule() -> Result<()> {
+        use run::output::*;
+
+        let metafield = r#"{ "value": "{\"maxPriceChange\": 0.25, \"markets\": {\"eu\": {}}, \"currencies\": {\"EUR\": {\"maxPriceChange\": 1}}}" }"#;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(metafield).line("23.40", "EUR").country("DE", "eu").json(),
+        )?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(metafield).line("23.40", "EUR").country("IE", "ie").json(),
+        )?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(metafield).line("23.20", "EUR").country("DE", "eu").json(),
+        )?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(23.0)] });
+        Ok(())
+    }
+
+    const SELLING_PLAN_RULES: &str = r#"{ "value": "{\"sellingPlans\": {\"gid://shopify/SellingPlan/1\": {\"increment\": 0.25}, \"any\": {\"skip\": true}}}" }"#;
+
+    fn subscription_line(amount: &str, selling_plan_id: &str) -> serde_json::Value {
+        let mut line = cart_line(amount, "USD");
+        line["sellingPlanAllocation"] = serde_json::json!({ "sellingPlan": { "id": selling_plan_id } });
+        line
+    }
+
+    #[test]
+    fn test_selling_plan_rule_applies_to_subscription_lines() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(SELLING_PLAN_RULES)
+                .lines(vec![subscription_line("23.40", "gid://shopify/SellingPlan/1")])
+                .json(),
+        )?;
+        let expected = FunctionRunResult {
+            operations: vec![fixed_price_update(23.25)],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_any_selling_plan_rule_can_skip_subscription_lines() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(SELLING_PLAN_RULES)
+                .lines(vec![subscription_line("23.40", "gid://shopify/SellingPlan/2")])
+                .json(),
+        )?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(SELLING_PLAN_RULES).line("23.40", "USD").json(),
+        )?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(23.0)] });
+        Ok(())
+    }
+
+    fn b2b_buyer(company_id: &str, location_id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "customer": { "exemptByTag": false },
+            "purchasingCompany": {
+                "company": { "id": company_id },
+                "location": { "id": location_id }
+            }
+        })
+    }
+
+    #[test]
+    fn test_tagged_customer_is_exempt() -> Result<()> {
+        use run::output::*;
+
+        let buyer = serde_json::json!({ "customer": { "exemptByTag": true }, "purchasingCompany": null });
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"exemptCustomerTags\": [\"wholesale\"]}" }"#)
+                .line("23.40", "USD")
+                .buyer(buyer)
+                .json(),
+        )?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_company_and_location_exemptions() -> Result<()> {
+        use run::output::*;
+
+        let metafield = r#"{ "value": "{\"exemptCompanyIds\": [\"gid://shopify/Company/1\"], \"exemptCompanyLocationIds\": [\"gid://shopify/CompanyLocation/7\"]}" }"#;
+        let run_for = |company_id, location_id| {
+            run_function_with_input(
+                run,
+                &CartInput::new(metafield)
+                    .line("23.40", "USD")
+                    .buyer(b2b_buyer(company_id, location_id))
+                    .json(),
+            )
+        };
+
+        let result: FunctionRunResult = run_for("gid://shopify/Company/1", "gid://shopify/CompanyLocation/1")?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        let result: FunctionRunResult = run_for("gid://shopify/Company/2", "gid://shopify/CompanyLocation/7")?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        let result: FunctionRunResult = run_for("gid://shopify/Company/2", "gid://shopify/CompanyLocation/2")?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(23.0)] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_companies_can_be_exempt() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"exemptCompanies\": true}" }"#)
+                .line("23.40", "USD")
+                .buyer(b2b_buyer("gid://shopify/Company/3", "gid://shopify/CompanyLocation/3"))
+                .json(),
+        )?;
+        let expected = FunctionRunResult { operations: vec![] };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_title_suffix_shows_original_price() -> Result<()> {
+        use run::output::*;
+
+        let result = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"titleSuffix\": \"(rounded from {original} to {rounded})\"}" }"#)
+                .line("23.4", "USD")
+                .json(),
+        )?;
+        let expected = FunctionRunResult {
+            operations: vec![CartOperation::Update(UpdateOperation {
+                cart_line_id: "gid://shopify/CartLine/1".to_string(),
+                price: Some(UpdateOperationPriceAdjustment {
+                    adjustment: UpdateOperationPriceAdjustmentValue::FixedPricePerUnit(
+                        UpdateOperationFixedPricePerUnitAdjustment {
+                            amount: Decimal(23.0),
+                        },
+                    ),
+                }),
+                image: None,
+                title: Some("Snowboard (rounded from 23.40 to 23.00)".to_string()),
+            })],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_title_suffix_keeps_variant_title() -> Result<()> {
+        use run::output::*;
+
+        let mut large = cart_line("23.4", "USD");
+        large["merchandise"]["title"] = serde_json::json!("Large");
+        let mut only_variant = cart_line("23.4", "USD");
+        only_variant["id"] = serde_json::json!("gid://shopify/CartLine/2");
+        only_variant["merchandise"]["title"] = serde_json::json!("Default Title");
+
+        let result: FunctionRunResult = run_function_with_input(
+            run,
+            &CartInput::new(r#"{ "value": "{\"titleSuffix\": \"(rounded from {original})\"}" }"#)
+                .lines(vec![large, only_variant])
+                .json(),
+        )?;
+        let titles: Vec<Option<String>> = result
+            .operations
+            .into_iter()
+            .map(|operation| match operation {
+                CartOperation::Update(update) => update.title,
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            titles,
+            vec![
+                Some("Snowboard - Large (rounded from 23.40)".to_string()),
+                Some("Snowboard (rounded from 23.40)".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_totals_in_jpy_keep_whole_yen() -> Result<()> {
+        use run::output::*;
+
+        let metafield = r#"{ "value": "{\"currencies\": {\"JPY\": {\"target\": \"lineTotal\", \"increment\": 10}}, \"titleSuffix\": \"(was {original})\"}" }"#;
+
+        // 3 x 1234 = 3702 -> 3700 can't be split into whole yen without going
+        // back to 1234, so the line is left unchanged
+        let mut line = cart_line("1234", "JPY");
+        line["quantity"] = serde_json::json!(3);
+        let result = run_function_with_input(run, &CartInput::new(metafield).lines(vec![line.clone()]).json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![] });
+
+        // 4 x 1234 = 4936 -> 4930, charged as 4 x 1233
+        line["quantity"] = serde_json::json!(4);
+        let result = run_function_with_input(run, &CartInput::new(metafield).lines(vec![line]).json())?;
+        let expected = FunctionRunResult {
+            operations: vec![CartOperation::Update(UpdateOperation {
+                cart_line_id: "gid://shopify/CartLine/1".to_string(),
+                price: Some(UpdateOperationPriceAdjustment {
+                    adjustment: UpdateOperationPriceAdjustmentValue::FixedPricePerUnit(
+                        UpdateOperationFixedPricePerUnitAdjustment {
+                            amount: Decimal(1233.0),
+                        },
+                    ),
+                }),
+                image: None,
+                title: Some("Snowboard (was 1234)".to_string()),
+            })],
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    /// Runs every `fixtures/<case>.input.json` and compares the result with
+    /// `fixtures/<case>.output.json`. Run with `UPDATE_FIXTURES=1` to rewrite the
+    /// expected outputs after an intended behaviour change.
+    #[test]
+    fn test_golden_fixtures() -> Result<()> {
+        use run::output::*;
+
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let update = env::var_os("UPDATE_FIXTURES").is_some();
+
+        let mut inputs: Vec<_> = fs::read_dir(&fixtures)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        inputs.retain(|path| path.to_string_lossy().ends_with(".input.json"));
+        inputs.sort();
+        assert!(!inputs.is_empty(), "no fixtures found in {}", fixtures.display());
+
+        // Every cart is checked before failing, so one behaviour change lists
+        // all the fixtures it touches.
+        let mut mismatches = Vec::new();
+        for input_path in inputs {
+            let output_path = input_path.with_file_name(input_path.to_string_lossy().replace(".input.json", ".output.json"));
+            let result: FunctionRunResult = run_function_with_input(run, &fs::read_to_string(&input_path)?)?;
+            let operations = serde_json::to_value(&result)?;
+
+            if update {
+                fs::write(&output_path, serde_json::to_string_pretty(&operations)? + "\n")?;
+            } else if operations != serde_json::from_str::<serde_json::Value>(&fs::read_to_string(&output_path)?)? {
+                mismatches.push(format!("{}: got {}", output_path.display(), operations));
+            }
+        }
+
+        assert!(mismatches.is_empty(), "fixtures out of date:\n{}", mismatches.join("\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cost_and_map_metafields_keep_price_above_floor() -> Result<()> {
+        use run::output::*;
+
+        let metafield = r#"{ "value": "{\"ending\": 0.99, \"allowPriceIncrease\": true, \"shopCurrency\": \"USD\"}" }"#;
+
+        let mut line = cart_line("23.40", "USD");
+        line["merchandise"]["cost"] = serde_json::json!({ "value": "20.00" });
+        line["merchandise"]["minimumAdvertisedPrice"] = serde_json::json!({ "value": "{\"amount\": \"23.10\", \"currency_code\": \"USD\"}" });
+        let result = run_function_with_input(run, &CartInput::new(metafield).lines(vec![line]).json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(23.99)] });
+
+        let mut line = cart_line("23.40", "USD");
+        line["merchandise"]["cost"] = serde_json::json!({ "value": "20.00" });
+        let result = run_function_with_input(run, &CartInput::new(metafield).lines(vec![line]).json())?;
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(22.99)] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_floor_is_converted_to_presentment_currency() {
+        let merchandise: CartLineMerchandise = serde_json::from_value(serde_json::json!({
+            "__typename": "ProductVariant",
+            "id": "gid://shopify/ProductVariant/1",
+            "cost": { "value": "10.00" },
+            "minimumAdvertisedPrice": null,
+            "product": cart_line("1", "USD")["merchandise"]["product"].clone()
+        }))
+        .unwrap();
+
+        assert_eq!(price_floor(&merchandise, Decimal(1.5), None), rust_decimal::Decimal::from_str("15").ok());
+    }
+
+    #[test]
+    fn test_exact_amount_is_limited_by_f64_precision() {
+        assert_eq!(exact_amount(&Decimal(19.999999)), rust_decimal::Decimal::from_str("19.999999").ok());
+        assert_eq!(
+            exact_amount(&Decimal("99999999999999.5".parse().unwrap())),
+            rust_decimal::Decimal::from_str("99999999999999.5").ok()
+        );
+        // Past 15 significant digits the f64 Shopify sends has already rounded the amount
+        assert_eq!(
+            exact_amount(&Decimal("99999999999999999999.5".parse().unwrap())),
+            rust_decimal::Decimal::from_str("100000000000000000000").ok()
+        );
+        assert_eq!(exact_amount(&Decimal(1e30)), None);
+    }
+
+    #[test]
+    fn test_metafield_amount_formats() {
+        let money = r#"{"amount": "12.50", "currency_code": "USD"}"#;
+
+        assert_eq!(metafield_amount("12.50", None), rust_decimal::Decimal::from_str("12.50").ok());
+        assert_eq!(metafield_amount(money, Some("USD")), rust_decimal::Decimal::from_str("12.50").ok());
+        assert_eq!(metafield_amount(money, Some("EUR")), None);
+        assert_eq!(metafield_amount(money, None), None);
+        assert_eq!(metafield_amount("cheap", Some("USD")), None);
+    }
+
+    #[test]
+    fn test_money_floor_in_another_currency_is_ignored() -> Result<()> {
+        use run::output::*;
+
+        // 23.10 EUR is not 23.10 in the USD shop currency, so it doesn't lift the price to 23.99
+        let metafield = r#"{ "value": "{\"ending\": 0.99, \"allowPriceIncrease\": true, \"shopCurrency\": \"USD\"}" }"#;
+        let mut line = cart_line("23.40", "USD");
+        line["merchandise"]["minimumAdvertisedPrice"] = serde_json::json!({ "value": "{\"amount\": \"23.10\", \"currency_code\": \"EUR\"}" });
+        let result = run_function_with_input(run, &CartInput::new(metafield).lines(vec![line]).json())?;
+
+        assert_eq!(result, FunctionRunResult { operations: vec![fixed_price_update(22.99)] });
+        Ok(())
+    }
+
+    const PROPERTY_STRATEGIES: [&str; 5] = ["floor", "ceil", "halfUp", "halfEven", "truncate"];
+    const PROPERTY_INCREMENTS: [&str; 7] = ["0.01", "0.05", "0.1", "0.25", "1", "5", "10"];
+    const PROPERTY_ENDINGS: [&str; 4] = ["0", "0.99", "0.49", "9"];
+
+    /// A configuration built from quickcheck's raw values.
+    struct PropertyConfig {
+        config: serde_json::Value,
+        strategy: &'static str,
+        increment: rust_decimal::Decimal,
+        ending: rust_decimal::Decimal,
+    }
+
+    fn property_config(strategy: u8, increment: u8, ending: u8, allow_price_increase: bool, line_total: bool) -> PropertyConfig {
+        let strategy = PROPERTY_STRATEGIES[strategy as usize % PROPERTY_STRATEGIES.len()];
+        let increment = PROPERTY_INCREMENTS[increment as usize % PROPERTY_INCREMENTS.len()];
+        let ending = PROPERTY_ENDINGS[ending as usize % PROPERTY_ENDINGS.len()];
+        let config = serde_json::json!({
+            "strategy": strategy,
+            "increment": increment,
+            "ending": ending,
+            "allowPriceIncrease": allow_price_increase,
+            "target": if line_total { "lineTotal" } else { "unitPrice" }
+        });
+
+        PropertyConfig {
+            config,
+            strategy,
+            increment: rust_decimal::Decimal::from_str(increment).unwrap(),
+            ending: rust_decimal::Decimal::from_str(ending).unwrap(),
+        }
+    }
+
+    /// Runs the function on a single line and returns the unit prices it emits.
+    fn rounded_unit_prices(amount: rust_decimal::Decimal, quantity: i64, config: &serde_json::Value) -> Vec<rust_decimal::Decimal> {
+        use run::output::*;
+
+        let mut line = cart_line(&amount.to_string(), "USD");
+        line["quantity"] = serde_json::json!(quantity);
+        let metafield = serde_json::json!({ "value": config.to_string() }).to_string();
+
+        let result: FunctionRunResult = run_function_with_input(
+            run,
+            &CartInput::new(&metafield).lines(vec![line]).json(),
+        ).unwrap();
+        result
+            .operations
+            .iter()
+            .filter_map(|operation| match operation {
+                CartOperation::Update(UpdateOperation {
+                    price: Some(UpdateOperationPriceAdjustment {
+                        adjustment: UpdateOperationPriceAdjustmentValue::FixedPricePerUnit(adjustment),
+                    }),
+                    ..
+                }) => exact_amount(&adjustment.amount),
+                _ => None,
+            })
+            .collect()
+    }
+
+    quickcheck::quickcheck! {
+        fn prop_output_is_never_negative(cents: u32, quantity: u8, strategy: u8, increment: u8, ending: u8, allow_increase: bool, line_total: bool) -> bool {
+            let property = property_config(strategy, increment, ending, allow_increase, line_total);
+            let price = rust_decimal::Decimal::new(cents as i64, 2);
+
+            rounded_unit_prices(price, quantity as i64, &property.config).iter().all(|amount| !amount.is_sign_negative())
+        }
+
+        fn prop_rerunning_on_output_changes_nothing(cents: u32, quantity: u8, strategy: u8, increment: u8, ending: u8, allow_increase: bool) -> bool {
+            let property = property_config(strategy, increment, ending, allow_increase, false);
+            let price = rust_decimal::Decimal::new(cents as i64, 2);
+
+            rounded_unit_prices(price, quantity as i64, &property.config)
+                .iter()
+                .all(|rounded| rounded_unit_prices(*rounded, quantity as i64, &property.config).is_empty())
+        }
+
+        fn prop_respects_configured_direction(cents: u32, strategy: u8, increment: u8, ending: u8, allow_increase: bool) -> bool {
+            let property = property_config(strategy, increment, ending, allow_increase, false);
+            let price = rust_decimal::Decimal::new(cents as i64, 2);
+
+            rounded_unit_prices(price, 1, &property.config).iter().all(|rounded| {
+                let within_increase_rule = allow_increase || *rounded <= price;
+                let within_direction = match property.strategy {
+                    "floor" | "truncate" => *rounded < price,
+                    "ceil" => *rounded > price,
+                    _ => (*rounded - price).abs() * rust_decimal::Decimal::TWO <= property.increment,
+                };
+                within_increase_rule && within_direction
+            })
+        }
+
+        fn prop_line_already_on_grid_is_never_updated(steps: u32, quantity: u8, strategy: u8, increment: u8, ending: u8, allow_increase: bool) -> bool {
+            let property = property_config(strategy, increment, ending, allow_increase, false);
+            let price = property.increment * rust_decimal::Decimal::from(steps) + property.ending;
+
+            rounded_unit_prices(price, quantity as i64, &property.config).is_empty()
+        }
+    }
+}
@@ -0,0 +1,390 @@
+import React, { useState } from "react";
+import {
+  reactExtension,
+  useApi,
+  Text,
+  Box,
+  FunctionSettings,
+  Section,
+  NumberField,
+  TextField,
+  Select,
+  Checkbox,
+  Button,
+  BlockStack,
+  Banner,
+  InlineStack,
+} from "@shopify/ui-extensions-react/admin";
+
+// Generated from the Rust `Configuration` struct, so the options offered here
+// and the checks run before saving always match what the function accepts.
+import schema from "../../vol-discount-variant/configuration.schema.json";
+import { validate, enumValues, requiredAlternatives } from "./schema";
+
+const TARGET = "admin.discount-details.function-settings.render";
+const METAFIELD_NAMESPACE = "$app:vol-discount-variant";
+const METAFIELD_KEY = "function-configuration";
+
+const AGGREGATIONS = enumValues(schema.definitions.Aggregation, schema);
+const TIER_BASES = enumValues(schema.definitions.TierBasis, schema);
+const TIER_VALUE_FIELDS = requiredAlternatives(schema.definitions.Tier, schema);
+
+const LABELS = {
+  variant: "Each variant on its own",
+  product: "Variants of the same product together",
+  collection: "Products in the collections together",
+  quantity: "Quantity",
+  subtotal: "Subtotal",
+  percentage: "Percentage off",
+  amountOff: "Amount off each item",
+  unitPrice: "Final price of each item",
+};
+
+export default reactExtension(TARGET, async (api) => {
+  // The host passes the discount's metafields, with the app namespace already
+  // resolved, so the configuration is matched by key.
+  const metafield = api.data.metafields.find((metafield) => metafield.key === METAFIELD_KEY);
+
+  return <VolumeDiscountSettings initialConfiguration={parseConfiguration(metafield?.value)} />;
+});
+
+// Starting point for a discount without a saved configuration.
+const NEW_CONFIGURATION = { tiers: [{ minimumQuantity: 5, percentage: 10 }] };
+
+// What the function assumes for a field the configuration leaves out, used to
+// show such fields without writing them back.
+const FIELD_DEFAULTS = Object.fromEntries(
+  Object.entries(schema.properties).map(([field, property]) => [field, property.default]),
+);
+
+function parseConfiguration(value) {
+  if (!value) {
+    return NEW_CONFIGURATION;
+  }
+
+  try {
+    return JSON.parse(value);
+  } catch (error) {
+    // Start from an empty configuration rather than the starter tier, so a
+    // broken value is never replaced by a discount the merchant didn't set up.
+    console.error("Unable to parse the saved configuration:", error);
+    return {};
+  }
+}
+
+// Fills in the fields the function derives rules from but merchants shouldn't
+// have to maintain by hand.
+function normalizeConfiguration(configuration) {
+  if (configuration.segments === undefined && configuration.customerTags === undefined) {
+    return configuration;
+  }
+
+  const customerTags = (configuration.segments ?? [])
+    .map((segment) => segment.customerTag)
+    .filter((tag, index, tags) => tag && tags.indexOf(tag) === index);
+
+  return { ...configuration, customerTags };
+}
+
+function VolumeDiscountSettings({ initialConfiguration }) {
+  const [errors, setErrors] = useState([]);
+  const [configuration, setConfiguration] = useState(initialConfiguration);
+  // Only the fields the merchant changes are written, so the saved
+  // configuration never gains settings they didn't choose.
+  const shown = { ...FIELD_DEFAULTS, ...configuration };
+
+  const { applyMetafieldChange } = useApi(TARGET);
+
+  const onError = (error) => {
+    console.error("Error occurred:", error);
+    setErrors([{ path: "configuration", message: error }]);
+  };
+
+  const update = async (changes) => {
+    const next = normalizeConfiguration({ ...configuration, ...changes });
+    setConfiguration(next);
+
+    const validationErrors = validate(next, schema);
+    setErrors(validationErrors);
+    if (validationErrors.length > 0) {
+      // Invalid settings would make the function ignore the whole configuration,
+      // so they are only saved once they're fixed.
+      return;
+    }
+
+    const result = await applyMetafieldChange({
+      type: "updateMetafield",
+      namespace: METAFIELD_NAMESPACE,
+      key: METAFIELD_KEY,
+      value: JSON.stringify(next),
+      valueType: "json",
+    });
+
+    if (result.type === "error") {
+      setErrors([{ path: "configuration", message: result.message }]);
+    }
+  };
+
+  return (
+    // Note: FunctionSettings must be rendered for the host to receive metafield updates
+    <FunctionSettings onError={onError}>
+      <ErrorBanner errors={errors} />
+      <Section heading="Volume tiers">
+        <BlockStack gap="base" paddingBlock="large">
+          <InlineStack gap="base">
+            <Select
+              label="Tiers are based on"
+              value={shown.tierBasis}
+              options={TIER_BASES.map((value) => ({ value, label: LABELS[value] ?? value }))}
+              onChange={(tierBasis) => update({ tierBasis })}
+            />
+            <Select
+              label="Count toward a tier"
+              value={shown.aggregateBy}
+              options={AGGREGATIONS.map((value) => ({ value, label: LABELS[value] ?? value }))}
+              onChange={(aggregateBy) => update({ aggregateBy })}
+            />
+          </InlineStack>
+          {shown.aggregateBy === "collection" && (
+            <TextField
+              label="Collection IDs (comma separated)"
+              value={shown.collectionIds.join(", ")}
+              onChange={(value) => update({ collectionIds: splitList(value) })}
+            />
+          )}
+          <TierList
+            tiers={shown.tiers}
+            tierBasis={shown.tierBasis}
+            onChange={(tiers) => update({ tiers })}
+          />
+        </BlockStack>
+      </Section>
+      <Section heading="Customer segments">
+        <BlockStack gap="base" paddingBlock="large">
+          <Text>Buyers in a segment get its tiers instead. The first matching segment wins.</Text>
+          {shown.segments.map((segment, index) => (
+            <SegmentSettings
+              key={index}
+              segment={segment}
+              tierBasis={shown.tierBasis}
+              onChange={(changed) => update({ segments: replaceAt(shown.segments, index, changed) })}
+              onRemove={() => update({ segments: removeAt(shown.segments, index) })}
+            />
+          ))}
+          <Button
+            onClick={() =>
+              update({
+                segments: [...shown.segments, { customerTag: "wholesale", tiers: shown.tiers }],
+              })
+            }
+          >
+            Add segment
+          </Button>
+        </BlockStack>
+      </Section>
+      <Section heading="Messages">
+        <MessageSettings messages={shown.messages} onChange={(messages) => update({ messages })} />
+      </Section>
+    </FunctionSettings>
+  );
+}
+
+function TierList({ tiers, tierBasis, onChange }) {
+  const minimumField = tierBasis === "subtotal" ? "minimumSubtotal" : "minimumQuantity";
+
+  const updateTier = (index, tier) => onChange(replaceAt(tiers, index, tier));
+  const valueField = (tier) => TIER_VALUE_FIELDS.find((field) => tier[field] !== undefined) ?? TIER_VALUE_FIELDS[0];
+
+  return (
+    <BlockStack gap="base">
+      {tiers.map((tier, index) => {
+        const field = valueField(tier);
+        return (
+          <InlineStack key={index} gap="base" blockAlignment="end">
+            <NumberField
+              label={tierBasis === "subtotal" ? "Minimum subtotal" : "Minimum quantity"}
+              value={tier[minimumField]}
+              min={tierBasis === "subtotal" ? 0 : 1}
+              step={tierBasis === "subtotal" ? 0.01 : 1}
+              onChange={(value) => updateTier(index, withMinimum(tier, minimumField, Number(value)))}
+            />
+            <Select
+              label="Discount"
+              value={field}
+              options={TIER_VALUE_FIELDS.map((value) => ({ value, label: LABELS[value] ?? value }))}
+              onChange={(changed) => updateTier(index, withValue(tier, changed, tier[field]))}
+            />
+            <NumberField
+              label="Value"
+              value={tier[field]}
+              min={0}
+              step={0.01}
+              onChange={(value) => updateTier(index, withValue(tier, field, Number(value)))}
+            />
+            <Button variant="tertiary" tone="critical" onClick={() => onChange(removeAt(tiers, index))}>
+              Remove
+            </Button>
+          </InlineStack>
+        );
+      })}
+      <Button onClick={() => onChange([...tiers, nextTier(tiers, minimumField)])}>Add tier</Button>
+    </BlockStack>
+  );
+}
+
+function SegmentSettings({ segment, tierBasis, onChange, onRemove }) {
+  return (
+    <Box padding="base">
+      <BlockStack gap="base">
+        <InlineStack gap="base" blockAlignment="end">
+          <TextField
+            label="Customer tag"
+            value={segment.customerTag ?? ""}
+            onChange={(customerTag) => onChange({ ...segment, customerTag: customerTag || null })}
+          />
+          <TextField
+            label="Company IDs (comma separated)"
+            value={(segment.companyIds ?? []).join(", ")}
+            onChange={(value) => onChange({ ...segment, companyIds: splitList(value) })}
+          />
+          <Checkbox checked={segment.anyCompany ?? false} onChange={(anyCompany) => onChange({ ...segment, anyCompany })}>
+            All B2B companies
+          </Checkbox>
+          <Button variant="tertiary" tone="critical" onClick={onRemove}>
+            Remove segment
+          </Button>
+        </InlineStack>
+        <TierList tiers={segment.tiers ?? []} tierBasis={tierBasis} onChange={(tiers) => onChange({ ...segment, tiers })} />
+      </BlockStack>
+    </Box>
+  );
+}
+
+function MessageSettings({ messages, onChange }) {
+  const languages = Object.keys(messages);
+
+  const updateTemplates = (language, templates) => onChange({ ...messages, [language]: templates });
+  // Keeps the languages in order, so the rows don't move while one is renamed.
+  const renameLanguage = (language, renamed) =>
+    onChange(Object.fromEntries(Object.entries(messages).map(([key, value]) => [key === language ? renamed : key, value])));
+  const removeLanguage = (language) => {
+    const { [language]: _removed, ...rest } = messages;
+    onChange(rest);
+  };
+
+  return (
+    <BlockStack gap="base" paddingBlock="large">
+      <Text>
+        Use {"{discount}"}, {"{minimum}"}, {"{remaining}"}, {"{nextDiscount}"} and {"{nextMinimum}"} in the templates.
+        The "default" language covers every language without its own templates.
+      </Text>
+      {languages.map((language, index) => (
+        // Keyed by position so renaming a language doesn't remount its fields.
+        <InlineStack key={index} gap="base" blockAlignment="end">
+          <LanguageField
+            language={language}
+            taken={languages.filter((other) => other !== language)}
+            onRename={(renamed) => renameLanguage(language, renamed)}
+          />
+          <TextField
+            label="Message"
+            value={messages[language].applied ?? ""}
+            onChange={(applied) => updateTemplates(language, { ...messages[language], applied: applied || null })}
+          />
+          <TextField
+            label="Message when a higher tier exists"
+            value={messages[language].nextTier ?? ""}
+            onChange={(nextTier) => updateTemplates(language, { ...messages[language], nextTier: nextTier || null })}
+          />
+          <Button variant="tertiary" tone="critical" onClick={() => removeLanguage(language)}>
+            Remove
+          </Button>
+        </InlineStack>
+      ))}
+      <Button
+        onClick={() =>
+          updateTemplates(languages.includes("default") ? `language-${languages.length}` : "default", {
+            applied: "{discount} off for {minimum}+",
+            nextTier: "{discount} off for {minimum}+ — add {remaining} more for {nextDiscount}",
+          })
+        }
+      >
+        Add language
+      </Button>
+    </BlockStack>
+  );
+}
+
+// Renames a language only once the new name is free, so typing the name of
+// another language flags it instead of overwriting that language's templates.
+function LanguageField({ language, taken, onRename }) {
+  const [draft, setDraft] = useState(language);
+  const [saved, setSaved] = useState(language);
+  if (saved !== language) {
+    // Another row moved into this position, e.g. after a removal.
+    setSaved(language);
+    setDraft(language);
+  }
+
+  const error = !draft
+    ? "Enter a language code or default"
+    : draft !== language && taken.includes(draft)
+      ? `${draft} already has templates`
+      : undefined;
+
+  return (
+    <TextField
+      label="Language"
+      value={draft}
+      error={error}
+      onChange={(renamed) => {
+        setDraft(renamed);
+        if (renamed && !taken.includes(renamed)) {
+          onRename(renamed);
+        }
+      }}
+    />
+  );
+}
+
+function ErrorBanner({ errors }) {
+  if (errors.length === 0) return null;
+
+  return (
+    <Box paddingBlockEnd="large">
+      <Banner title="These settings can't be saved yet" tone="critical">
+        {errors.map(({ path, message }) => `${path} ${message}`).join("\n")}
+      </Banner>
+    </Box>
+  );
+}
+
+function withMinimum(tier, minimumField, minimum) {
+  const { minimumQuantity: _quantity, minimumSubtotal: _subtotal, ...rest } = tier;
+  return { ...rest, [minimumField]: minimum };
+}
+
+function withValue(tier, field, value) {
+  const rest = Object.fromEntries(Object.entries(tier).filter(([key]) => !TIER_VALUE_FIELDS.includes(key)));
+  return { ...rest, [field]: value };
+}
+
+function nextTier(tiers, minimumField) {
+  const highest = Math.max(0, ...tiers.map((tier) => tier[minimumField] ?? 0));
+  return { [minimumField]: highest + (minimumField === "minimumQuantity" ? 5 : 100), percentage: 10 };
+}
+
+function splitList(value) {
+  return value
+    .split(",")
+    .map((item) => item.trim())
+    .filter(Boolean);
+}
+
+function replaceAt(items, index, item) {
+  return items.map((existing, i) => (i === index ? item : existing));
+}
+
+function removeAt(items, index) {
+  return items.filter((_, i) => i !== index);
+}
@@ -0,0 +1,152 @@
+// Checks a configuration against the JSON schema generated from the Rust
+// `Configuration` struct (vol-discount-variant/configuration.schema.json).
+// Only the draft-07 keywords that schema uses are supported. Returns a list of
+// `{ path, message }` errors, with paths like `tiers[1].percentage`.
+export function validate(value, schema, root = schema, path = "") {
+  schema = resolve(schema, root);
+  const errors = [];
+  const fail = (message) => errors.push({ path: path || "configuration", message });
+
+  if (schema.type && !matchesType(value, schema.type)) {
+    fail(`must be ${[].concat(schema.type).join(" or ")}`);
+    return errors;
+  }
+  if (schema.enum && !schema.enum.includes(value)) {
+    fail(`must be one of ${schema.enum.join(", ")}`);
+  }
+  if (typeof value === "number") {
+    if (schema.minimum !== undefined && value < schema.minimum) {
+      fail(`must be at least ${schema.minimum}`);
+    }
+    if (schema.exclusiveMinimum !== undefined && value <= schema.exclusiveMinimum) {
+      fail(`must be greater than ${schema.exclusiveMinimum}`);
+    }
+    if (schema.maximum !== undefined && value > schema.maximum) {
+      fail(`must be at most ${schema.maximum}`);
+    }
+  }
+  if (Array.isArray(value) && schema.minItems !== undefined && value.length < schema.minItems) {
+    fail(`must have at least ${schema.minItems} item(s)`);
+  }
+
+  for (const branch of schema.allOf ?? []) {
+    errors.push(...validate(value, branch, root, path));
+  }
+  if (schema.anyOf) {
+    errors.push(...validateAnyOf(value, schema.anyOf, root, path));
+  }
+  if (schema.oneOf) {
+    errors.push(...validateOneOf(value, schema.oneOf, root, path));
+  }
+  if (schema.if) {
+    const branch = validate(value, schema.if, root, path).length === 0 ? schema.then : schema.else;
+    if (branch) {
+      errors.push(...validate(value, branch, root, path));
+    }
+  }
+
+  if (isObject(value)) {
+    for (const key of schema.required ?? []) {
+      if (value[key] === undefined) {
+        errors.push({ path: join(path, key), message: "is required" });
+      }
+    }
+    for (const [key, property] of Object.entries(value)) {
+      if (schema.properties?.[key]) {
+        errors.push(...validate(property, schema.properties[key], root, join(path, key)));
+      } else if (isObject(schema.additionalProperties)) {
+        errors.push(...validate(property, schema.additionalProperties, root, join(path, key)));
+      } else if (schema.additionalProperties === false) {
+        errors.push({ path: join(path, key), message: "is not a known setting" });
+      }
+    }
+  }
+  if (Array.isArray(value) && schema.items) {
+    value.forEach((item, index) => {
+      errors.push(...validate(item, schema.items, root, `${path}[${index}]`));
+    });
+  }
+
+  return errors;
+}
+
+// The values allowed by a `oneOf` of single-value enums, such as `aggregateBy`.
+export function enumValues(schema, root = schema) {
+  return (resolve(schema, root).oneOf ?? []).flatMap((branch) => resolve(branch, root).enum ?? []);
+}
+
+// The fields a `oneOf` or `anyOf` of required fields chooses between, such as a
+// tier's `percentage`, `amountOff` or `unitPrice`.
+export function requiredAlternatives(schema, root = schema) {
+  const resolved = resolve(schema, root);
+  return (resolved.oneOf ?? resolved.anyOf ?? []).flatMap((branch) => resolve(branch, root).required ?? []);
+}
+
+function validateAnyOf(value, branches, root, path) {
+  if (branches.some((branch) => validate(value, branch, root, path).length === 0)) {
+    return [];
+  }
+
+  const alternatives = requiredAlternatives({ anyOf: branches }, root);
+  const message = alternatives.length
+    ? `must set one of ${alternatives.join(", ")}`
+    : `must be one of ${enumValues({ oneOf: branches }, root).join(", ")}`;
+  return [{ path: path || "configuration", message }];
+}
+
+function validateOneOf(value, branches, root, path) {
+  const results = branches.map((branch) => validate(value, branch, root, path));
+  const matching = results.filter((errors) => errors.length === 0).length;
+  if (matching === 1) {
+    return [];
+  }
+
+  // Report the errors of the branch the value was meant for, e.g. a tier with
+  // a `percentage` above 100, rather than a generic message.
+  const chosen = branches.findIndex((branch) =>
+    (resolve(branch, root).required ?? []).some((key) => value?.[key] !== undefined),
+  );
+  if (matching === 0 && chosen >= 0) {
+    return results[chosen];
+  }
+
+  const alternatives = requiredAlternatives({ oneOf: branches }, root);
+  const message = alternatives.length
+    ? `must set exactly one of ${alternatives.join(", ")}`
+    : `must be one of ${enumValues({ oneOf: branches }, root).join(", ")}`;
+  return [{ path: path || "configuration", message }];
+}
+
+function resolve(schema, root) {
+  if (schema?.$ref) {
+    return resolve(root.definitions[schema.$ref.replace("#/definitions/", "")], root);
+  }
+  return schema ?? {};
+}
+
+function matchesType(value, type) {
+  return [].concat(type).some((name) => {
+    switch (name) {
+      case "integer":
+        return Number.isInteger(value);
+      case "number":
+        return typeof value === "number" && Number.isFinite(value);
+      case "null":
+        return value === null;
+      case "object":
+        return isObject(value);
+      case "array":
+        return Array.isArray(value);
+      default:
+        return typeof value === name;
+    }
+  });
+}
+
+function isObject(value) {
+  return typeof value === "object" && value !== null && !Array.isArray(value);
+}
+
+function join(path, key) {
+  return path ? `${path}.${key}` : key;
+}
@@ -0,0 +1,17 @@
+// Runs the cases shared with the function's `cargo test` through `validate`, so
+// schema.js and the function's src/schema.rs can't drift apart unnoticed.
+import { test } from "node:test";
+import assert from "node:assert/strict";
+import { readFileSync } from "node:fs";
+
+import { validate } from "./schema.js";
+
+const read = (file) => JSON.parse(readFileSync(new URL(`../../vol-discount-variant/${file}`, import.meta.url), "utf8"));
+const schema = read("configuration.schema.json");
+const byPath = (a, b) => `${a.path} ${a.message}`.localeCompare(`${b.path} ${b.message}`);
+
+for (const { name, configuration, errors } of read("configuration.cases.json")) {
+  test(name, () => {
+    assert.deepEqual(validate(configuration, schema).sort(byPath), [...errors].sort(byPath));
+  });
+}
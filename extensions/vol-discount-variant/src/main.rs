@@ -1,5 +1,8 @@
 use std::process;
+pub mod messages;
 pub mod run;
+pub mod schema;
+pub mod tiers;
 
 fn main() {
     eprintln!("Please invoke a named export.");
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Key in `messages` used when there are no templates for the buyer's language.
+pub const DEFAULT_LANGUAGE: &str = "default";
+
+/// Discount message templates for one language. `{discount}`, `{minimum}`,
+/// `{remaining}`, `{nextDiscount}` and `{nextMinimum}` are replaced with the
+/// values of the tier the buyer reached and of the next one.
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all(deserialize = "camelCase"), default, deny_unknown_fields)]
+pub struct MessageTemplates {
+    /// Shown on the highest tier, e.g. `{discount} off for {minimum}+`.
+    pub applied: Option<String>,
+    /// Shown while a higher tier is within reach, e.g.
+    /// `{discount} off for {minimum}+ — add {remaining} more for {nextDiscount}`.
+    /// Falls back to `applied`.
+    pub next_tier: Option<String>,
+}
+
+impl MessageTemplates {
+    /// Picks the template to show, depending on whether there is a next tier.
+    pub fn template(&self, has_next_tier: bool) -> Option<&str> {
+        let next_tier = self.next_tier.as_deref().filter(|_| has_next_tier);
+        next_tier.or(self.applied.as_deref())
+    }
+}
+
+/// Picks the templates for a Shopify `LanguageCode` such as `FR` or `PT_BR`.
+/// Keys are matched case-insensitively and may use `-`, so `pt-BR` works too.
+/// A regional language falls back to its base language, then to `default`.
+pub fn templates_for<'a>(messages: &'a HashMap<String, MessageTemplates>, language: &str) -> Option<&'a MessageTemplates> {
+    let normalize = |code: &str| code.to_lowercase().replace('-', "_");
+    let find = |code: &str| {
+        messages
+            .iter()
+            .find(|(key, _)| normalize(key) == code)
+            .map(|(_, templates)| templates)
+    };
+
+    let language = normalize(language);
+    let base_language = language.split('_').next().unwrap_or_default();
+    find(&language).or_else(|| find(base_language)).or_else(|| find(DEFAULT_LANGUAGE))
+}
+
+/// Replaces every `{name}` in `template` with its value.
+pub fn render(template: &str, placeholders: &[(&str, String)]) -> String {
+    placeholders
+        .iter()
+        .fold(template.to_string(), |message, (name, value)| message.replace(&format!("{{{}}}", name), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates(applied: &str) -> MessageTemplates {
+        MessageTemplates { applied: Some(applied.to_string()), next_tier: None }
+    }
+
+    #[test]
+    fn test_templates_for_falls_back_to_base_language_and_default() {
+        let messages = HashMap::from([
+            ("default".to_string(), templates("default")),
+            ("fr".to_string(), templates("fr")),
+            ("pt-BR".to_string(), templates("pt-BR")),
+        ]);
+
+        assert_eq!(templates_for(&messages, "FR"), Some(&templates("fr")));
+        assert_eq!(templates_for(&messages, "FR_CA"), Some(&templates("fr")));
+        assert_eq!(templates_for(&messages, "PT_BR"), Some(&templates("pt-BR")));
+        assert_eq!(templates_for(&messages, "DE"), Some(&templates("default")));
+        assert_eq!(templates_for(&HashMap::new(), "EN"), None);
+    }
+
+    #[test]
+    fn test_next_tier_template_falls_back_to_applied() {
+        let templates = MessageTemplates {
+            applied: Some("applied".to_string()),
+            next_tier: Some("next".to_string()),
+        };
+
+        assert_eq!(templates.template(true), Some("next"));
+        assert_eq!(templates.template(false), Some("applied"));
+        assert_eq!(MessageTemplates { next_tier: None, ..templates }.template(true), Some("applied"));
+    }
+
+    #[test]
+    fn test_render_replaces_every_placeholder() {
+        let message = render(
+            "{discount} off for {minimum}+ — add {remaining} more for {nextDiscount}",
+            &[
+                ("discount", "10%".to_string()),
+                ("minimum", "5".to_string()),
+                ("remaining", "5".to_string()),
+                ("nextDiscount", "15%".to_string()),
+            ],
+        );
+
+        assert_eq!(message, "10% off for 5+ — add 5 more for 15%");
+    }
+}
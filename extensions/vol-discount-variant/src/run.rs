@@ -1,15 +1,234 @@
+use run::input::InputCartBuyerIdentity as BuyerIdentity;
+use run::input::InputCartLines as CartLine;
+use run::input::InputCartLinesMerchandise as CartLineMerchandise;
+use run::input::InputCartLinesMerchandiseOnProductVariant as CartLineVariant;
+use run::output::Discount;
+use run::output::FixedAmount;
+use run::output::Percentage;
+use run::output::ProductVariantTarget;
+use run::output::Target;
+use run::output::Value;
 use shopify_function::prelude::*;
 use shopify_function::Result;
 
+use crate::messages::{render, templates_for, MessageTemplates};
+use crate::schema;
+use crate::tiers::{best_tier, next_tier, Progress, Tier, TierBasis, TierValue};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::RoundingStrategy;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Default, PartialEq)]
-#[serde(rename_all(deserialize = "camelCase"))]
-struct Configuration {}
+/// Which lines count toward the same tier.
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+enum Aggregation {
+    /// Each variant reaches its tier on its own.
+    #[default]
+    Variant,
+    /// Variants of the same product count together, e.g. three sizes of a shirt.
+    Product,
+    /// Every product in any of `collectionIds` counts toward one pool, e.g. "any
+    /// 6 candles". Products outside the collections aren't discounted.
+    Collection,
+}
+
+/// Key of the single group used when aggregating by collection.
+const COLLECTION_POOL: &str = "collection";
+
+/// Currencies without minor units, e.g. `JPY`. Amounts in them are shown without
+/// decimals in messages.
+const ZERO_DECIMAL_CURRENCIES: [&str; 16] = [
+    "BIF", "CLP", "DJF", "GNF", "ISK", "JPY", "KMF", "KRW", "PYG", "RWF", "UGX", "VND", "VUV", "XAF", "XOF", "XPF",
+];
+
+/// Currencies with three decimals, e.g. `KWD`.
+const THREE_DECIMAL_CURRENCIES: [&str; 7] = ["BHD", "IQD", "JOD", "KWD", "LYD", "OMR", "TND"];
+
+/// Settings stored as JSON in the discount's `function-configuration` metafield,
+/// e.g. `{"tiers": [{"minimumQuantity": 5, "percentage": 10}]}`. Each tier sets
+/// one of `percentage`, `amountOff` or `unitPrice`. The top-level tier table
+/// applies to anonymous buyers and to buyers outside every segment.
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all(deserialize = "camelCase"), default, deny_unknown_fields)]
+struct Configuration {
+    /// Tiers for every variant without its own entry in `variants`.
+    tiers: Vec<Tier>,
+    /// Tiers keyed by product variant ID.
+    variants: HashMap<String, Vec<Tier>>,
+    /// Tiers keyed by product ID, used when aggregating by product.
+    products: HashMap<String, Vec<Tier>>,
+    aggregate_by: Aggregation,
+    tier_basis: TierBasis,
+    /// Collections pooled together when aggregating by collection. Also an input
+    /// query variable, so Shopify resolves `inAnyCollection` for them.
+    collection_ids: Vec<String>,
+    /// Tier tables for groups of buyers. The first segment the buyer matches wins.
+    segments: Vec<Segment>,
+    /// Every tag used by a segment. Also an input query variable, so Shopify
+    /// resolves `hasTags` for them.
+    customer_tags: Vec<String>,
+    /// Discount message templates keyed by language code, e.g. `en` or `fr`,
+    /// with `default` for every other language.
+    messages: HashMap<String, MessageTemplates>,
+}
+
+/// The tiers offered to one group of buyers, borrowed from the configuration or
+/// from a segment. Both spell the fields out rather than sharing a flattened
+/// struct, which would lose the field path of parse errors.
+struct TierTable<'a> {
+    tiers: &'a [Tier],
+    variants: &'a HashMap<String, Vec<Tier>>,
+    products: &'a HashMap<String, Vec<Tier>>,
+}
+
+/// A group of buyers with their own tiers, such as wholesale customers. The
+/// buyer is in the segment when they match any of its fields.
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all(deserialize = "camelCase"), default, deny_unknown_fields)]
+struct Segment {
+    /// Customers with this tag. It must also be listed in `customerTags`.
+    customer_tag: Option<String>,
+    /// Every buyer purchasing on behalf of a B2B company.
+    any_company: bool,
+    /// Buyers purchasing for one of these companies.
+    company_ids: Vec<String>,
+    tiers: Vec<Tier>,
+    variants: HashMap<String, Vec<Tier>>,
+    products: HashMap<String, Vec<Tier>>,
+}
+
+impl Segment {
+    fn table(&self) -> TierTable<'_> {
+        TierTable {
+            tiers: &self.tiers,
+            variants: &self.variants,
+            products: &self.products,
+        }
+    }
+
+    fn matches(&self, buyer_identity: &BuyerIdentity) -> bool {
+        let tagged = match (&self.customer_tag, &buyer_identity.customer) {
+            (Some(tag), Some(customer)) => customer
+                .has_tags
+                .iter()
+                .any(|response| response.has_tag && &response.tag == tag),
+            _ => false,
+        };
+        let company = buyer_identity.purchasing_company.as_ref().map_or(false, |purchasing_company| {
+            self.any_company || self.company_ids.contains(&purchasing_company.company.id)
+        });
+
+        tagged || company
+    }
+}
+
+impl<'a> TierTable<'a> {
+    fn tiers_for(&self, aggregate_by: Aggregation, group_key: &str) -> &'a [Tier] {
+        let tiers = match aggregate_by {
+            Aggregation::Variant => self.variants,
+            Aggregation::Product => self.products,
+            Aggregation::Collection => return self.tiers,
+        };
+        tiers.get(group_key).map_or(self.tiers, Vec::as_slice)
+    }
+}
+
+/// Why the `function-configuration` metafield was rejected. `path` points at the
+/// offending field, e.g. `tiers[1].percentage`.
+#[derive(Debug, PartialEq)]
+enum ConfigurationError {
+    /// The value isn't JSON or doesn't match the shape of `Configuration`.
+    Malformed { path: String, message: String },
+    /// The value parses but describes a discount that can't be applied.
+    Invalid { path: String, message: String },
+    /// The embedded `configuration.schema.json` couldn't be read, so the value
+    /// can't be checked and isn't trusted.
+    Schema { message: String },
+}
+
+impl fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigurationError::Malformed { path, message } => write!(f, "malformed field `{}`: {}", path, message),
+            ConfigurationError::Invalid { path, message } => write!(f, "invalid field `{}`: {}", path, message),
+            ConfigurationError::Schema { message } => write!(f, "unreadable configuration schema: {}", message),
+        }
+    }
+}
+
+/// The schema generated from `Configuration`, shared with the settings UI. It
+/// holds the bounds of every field and the rules between fields, such as which
+/// minimum a tier needs, so neither side spells them out again.
+const CONFIGURATION_SCHEMA: &str = include_str!("../configuration.schema.json");
 
 impl Configuration {
-    fn from_str(value: &str) -> Self {
-        serde_json::from_str(value).expect("Unable to parse configuration value from metafield")
+    fn from_str(value: &str) -> std::result::Result<Self, ConfigurationError> {
+        let malformed = |error: serde_path_to_error::Error<serde_json::Error>| ConfigurationError::Malformed {
+            path: error.path().to_string(),
+            message: error.inner().to_string(),
+        };
+        let value: serde_json::Value =
+            serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(value)).map_err(malformed)?;
+        let config: Self = serde_path_to_error::deserialize(&value).map_err(malformed)?;
+
+        let schema = serde_json::from_str(CONFIGURATION_SCHEMA).map_err(|error| ConfigurationError::Schema {
+            message: error.to_string(),
+        })?;
+        if let Some(error) = schema::validate(&value, &schema).into_iter().next() {
+            return Err(ConfigurationError::Invalid {
+                path: error.path,
+                message: error.message,
+            });
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks what the schema can't express: every segment tag is listed in
+    /// `customerTags`, the input query variable Shopify resolves `hasTags` for.
+    fn validate(&self) -> std::result::Result<(), ConfigurationError> {
+        for (index, segment) in self.segments.iter().enumerate() {
+            if let Some(tag) = &segment.customer_tag {
+                if !self.customer_tags.contains(tag) {
+                    return Err(ConfigurationError::Invalid {
+                        path: format!("segments[{}].customerTag", index),
+                        message: format!("`{}` must also be listed in customerTags", tag),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Key of the group a variant's quantity counts toward, or `None` if it
+    /// doesn't take part in the discount.
+    fn group_key<'a>(&self, variant: &'a CartLineVariant) -> Option<&'a str> {
+        match self.aggregate_by {
+            Aggregation::Variant => Some(&variant.id),
+            Aggregation::Product => Some(&variant.product.id),
+            Aggregation::Collection => variant.product.in_collection.then_some(COLLECTION_POOL),
+        }
+    }
+
+    fn default_table(&self) -> TierTable<'_> {
+        TierTable {
+            tiers: &self.tiers,
+            variants: &self.variants,
+            products: &self.products,
+        }
+    }
+
+    /// The tier table of the first segment the buyer is in, or the default one.
+    fn table_for(&self, buyer_identity: Option<&BuyerIdentity>) -> TierTable<'_> {
+        buyer_identity
+            .and_then(|buyer_identity| self.segments.iter().find(|segment| segment.matches(buyer_identity)))
+            .map_or_else(|| self.default_table(), Segment::table)
     }
 }
 
@@ -20,21 +239,344 @@ fn run(input: input::ResponseData) -> Result<output::FunctionRunResult> {
         discount_application_strategy: output::DiscountApplicationStrategy::FIRST,
     };
 
-    let _config = match input.discount_node.metafield {
-        Some(input::InputDiscountNodeMetafield { value }) => Configuration::from_str(&value),
+    let config = match input.discount_node.metafield {
+        Some(input::InputDiscountNodeMetafield { value }) => match Configuration::from_str(&value) {
+            Ok(config) => config,
+            Err(error) => {
+                eprintln!("Ignoring function configuration, {}", error);
+                return Ok(no_discount);
+            }
+        },
         None => return Ok(no_discount),
     };
 
+    let presentment_currency_rate = match exact_amount(&input.presentment_currency_rate) {
+        Some(rate) => rate,
+        None => {
+            eprintln!("Unable to read presentment currency rate {}", input.presentment_currency_rate.as_f64());
+            return Ok(no_discount);
+        }
+    };
+
+    let table = config.table_for(input.cart.buyer_identity.as_ref());
+    let templates = templates_for(&config.messages, &input.localization.language.iso_code);
+    let discounts = get_discounts(&input.cart.lines, presentment_currency_rate, &config, &table, templates);
+    if discounts.is_empty() {
+        return Ok(no_discount);
+    }
+
+    // Every discount targets different variants, so all of them can apply.
     Ok(output::FunctionRunResult {
-        discounts: vec![],
-        discount_application_strategy: output::DiscountApplicationStrategy::FIRST,
+        discounts,
+        discount_application_strategy: output::DiscountApplicationStrategy::ALL,
+    })
+}
+
+/// Quantities of the lines that count toward the same tier.
+struct Group<'a> {
+    key: &'a str,
+    progress: Progress,
+    /// Currency of the lines, which is the cart's currency.
+    currency_code: &'a str,
+    /// Contributing variants with the lowest unit price among their lines, so a
+    /// fixed final price never takes any of those lines below it.
+    variants: Vec<(&'a str, rust_decimal::Decimal)>,
+}
+
+/// Builds the discounts for every group of lines that reaches a tier,
+/// targeting each variant that contributed to it. Lines of the same variant are
+/// always counted together, since a discount targets the variant rather than a
+/// single line.
+fn get_discounts(
+    lines: &[CartLine],
+    presentment_currency_rate: rust_decimal::Decimal,
+    config: &Configuration,
+    table: &TierTable,
+    templates: Option<&MessageTemplates>,
+) -> Vec<Discount> {
+    let mut groups: Vec<Group> = Vec::new();
+    for line in lines {
+        let variant = match &line.merchandise {
+            CartLineMerchandise::ProductVariant(variant) => variant,
+            CartLineMerchandise::CustomProduct => continue,
+        };
+        let unit_price = match exact_amount(&line.cost.amount_per_quantity.amount) {
+            Some(unit_price) => unit_price,
+            None => {
+                eprintln!("Unable to read price on line {}", line.id);
+                continue;
+            }
+        };
+
+        let key = match config.group_key(variant) {
+            Some(key) => key,
+            None => continue,
+        };
+        let group = match groups.iter_mut().position(|group| group.key == key) {
+            Some(index) => &mut groups[index],
+            None => {
+                groups.push(Group {
+                    key,
+                    progress: Progress::default(),
+                    currency_code: &line.cost.amount_per_quantity.currency_code,
+                    variants: vec![],
+                });
+                groups.last_mut().unwrap()
+            }
+        };
+        group.progress.quantity = group.progress.quantity.saturating_add(line.quantity);
+        // A subtotal too large to represent still reaches every tier, so it saturates
+        let line_subtotal = unit_price.saturating_mul(rust_decimal::Decimal::from(line.quantity));
+        group.progress.subtotal = group.progress.subtotal.saturating_add(line_subtotal);
+        match group.variants.iter_mut().find(|(id, _)| *id == variant.id) {
+            Some((_, lowest_unit_price)) => *lowest_unit_price = (*lowest_unit_price).min(unit_price),
+            None => group.variants.push((&variant.id, unit_price)),
+        }
+    }
+
+    groups
+        .into_iter()
+        .flat_map(|group| {
+            let tiers = table.tiers_for(config.aggregate_by, group.key);
+            let tier = match best_tier(tiers, config.tier_basis, &group.progress, presentment_currency_rate) {
+                Some(tier) => tier,
+                None => return vec![],
+            };
+            let value = match tier.value.in_currency(presentment_currency_rate) {
+                Some(value) => value,
+                None => {
+                    eprintln!("Skipping {}: {} is too large to represent in the cart's currency", group.key, tier.value.field());
+                    return vec![];
+                }
+            };
+
+            let message = templates.and_then(|templates| {
+                let next = next_tier(tiers, config.tier_basis, &group.progress, presentment_currency_rate);
+                let template = templates.template(next.is_some())?;
+                let placeholders = message_placeholders(
+                    tier,
+                    next,
+                    config.tier_basis,
+                    &group.progress,
+                    presentment_currency_rate,
+                    group.currency_code,
+                )?;
+                Some(render(template, &placeholders))
+            });
+            tier_discounts(&value, &group.variants, message)
+        })
+        .collect()
+}
+
+/// Turns a tier value, already in the cart's currency, into discounts on
+/// `variants`. A fixed final price takes a different amount off each variant, so
+/// it gets one discount per variant; the other values share a single discount.
+fn tier_discounts(value: &TierValue, variants: &[(&str, rust_decimal::Decimal)], message: Option<String>) -> Vec<Discount> {
+    let all_variants = || variants.iter().map(|(id, _)| variant_target(id)).collect();
+
+    match value {
+        TierValue::Percentage(percentage) => vec![Discount {
+            message,
+            targets: all_variants(),
+            value: Value::Percentage(Percentage {
+                value: Decimal(percentage.to_f64().unwrap_or_default()),
+            }),
+        }],
+        TierValue::AmountOff(amount) => vec![Discount {
+            message,
+            targets: all_variants(),
+            value: fixed_amount_per_item(*amount),
+        }],
+        TierValue::UnitPrice(price) => {
+            variants
+                .iter()
+                .filter(|(_, unit_price)| unit_price > price)
+                .map(|(id, unit_price)| Discount {
+                    message: message.clone(),
+                    targets: vec![variant_target(id)],
+                    value: fixed_amount_per_item(unit_price - price),
+                })
+                .collect()
+        }
+    }
+}
+
+/// Values for the message placeholders, in the cart's currency, or `None` if an
+/// amount is too large to represent in it. `{discount}` is the percentage, the
+/// amount off, or the final unit price of the tier.
+fn message_placeholders(
+    tier: &Tier,
+    next: Option<&Tier>,
+    basis: TierBasis,
+    progress: &Progress,
+    presentment_currency_rate: rust_decimal::Decimal,
+    currency_code: &str,
+) -> Option<Vec<(&'static str, String)>> {
+    let describe_value = |value: &TierValue| match value.in_currency(presentment_currency_rate)? {
+        TierValue::Percentage(percentage) => Some(format!("{}%", percentage.normalize())),
+        TierValue::AmountOff(amount) | TierValue::UnitPrice(amount) => {
+            Some(format_amount(amount, currency_code, RoundingStrategy::MidpointNearestEven))
+        }
+    };
+    let describe_minimum = |minimum: rust_decimal::Decimal| match basis {
+        TierBasis::Quantity => minimum.to_string(),
+        TierBasis::Subtotal => format_amount(minimum, currency_code, RoundingStrategy::ToPositiveInfinity),
+    };
+
+    let mut placeholders = vec![
+        ("discount", describe_value(&tier.value)?),
+        ("minimum", describe_minimum(basis.minimum(tier, presentment_currency_rate)?)),
+    ];
+    if let Some(next) = next {
+        let next_minimum = basis.minimum(next, presentment_currency_rate)?;
+        placeholders.push(("nextDiscount", describe_value(&next.value)?));
+        placeholders.push(("nextMinimum", describe_minimum(next_minimum)));
+        placeholders.push(("remaining", describe_minimum(next_minimum.checked_sub(basis.reached(progress))?)));
+    }
+    Some(placeholders)
+}
+
+/// Shows `amount` with as many decimals as `currency_code` has, e.g. `1500` in
+/// JPY and `12.50` in USD. Thresholds and remaining amounts are rounded up with
+/// `RoundingStrategy::ToPositiveInfinity`, so spending what a message says always
+/// reaches the tier.
+fn format_amount(amount: rust_decimal::Decimal, currency_code: &str, rounding: RoundingStrategy) -> String {
+    let decimal_places = if ZERO_DECIMAL_CURRENCIES.contains(&currency_code) {
+        0
+    } else if THREE_DECIMAL_CURRENCIES.contains(&currency_code) {
+        3
+    } else {
+        2
+    };
+    format!("{:.*}", decimal_places, amount.round_dp_with_strategy(decimal_places as u32, rounding))
+}
+
+fn variant_target(id: &str) -> Target {
+    Target::ProductVariant(ProductVariantTarget {
+        id: id.to_string(),
+        quantity: None,
+    })
+}
+
+fn fixed_amount_per_item(amount: rust_decimal::Decimal) -> Value {
+    Value::FixedAmount(FixedAmount {
+        amount: Decimal(amount.normalize().to_f64().unwrap_or_default()),
+        applies_to_each_item: Some(true),
     })
 }
 
+/// Converts a price or rate from Shopify's `f64`-backed `Decimal` for tier
+/// arithmetic, so `10.00 - 4.50` can't come out as `5.4999…`. Only the first 15
+/// or so significant digits of a price survive the `f64`.
+fn exact_amount(amount: &Decimal) -> Option<rust_decimal::Decimal> {
+    rust_decimal::Decimal::from_str(&amount.as_f64().to_string()).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use shopify_function::{run_function_with_input, Result};
+    use std::{env, fs, path::Path};
+
+    /// Function input for a test cart. Tests set the fields they care about and
+    /// take the rest from `Default`: no lines, a rate of 1.0, an anonymous
+    /// English-speaking buyer and no metafield.
+    struct Input<'a> {
+        lines: serde_json::Value,
+        presentment_currency_rate: &'a str,
+        buyer_identity: serde_json::Value,
+        language: &'a str,
+        metafield: &'a str,
+    }
+
+    impl Default for Input<'_> {
+        fn default() -> Self {
+            Input {
+                lines: serde_json::json!([]),
+                presentment_currency_rate: "1.0",
+                buyer_identity: serde_json::Value::Null,
+                language: "EN",
+                metafield: "null",
+            }
+        }
+    }
+
+    impl Input<'_> {
+        fn json(&self) -> String {
+            format!(
+                r#"{{ "cart": {{ "lines": {}, "buyerIdentity": {} }}, "presentmentCurrencyRate": "{}", "localization": {{ "language": {{ "isoCode": "{}" }} }}, "discountNode": {{ "metafield": {} }} }}"#,
+                self.lines, self.buyer_identity, self.presentment_currency_rate, self.language, self.metafield
+            )
+        }
+    }
+
+    fn buyer(tags: &[&str], company_id: Option<&str>) -> serde_json::Value {
+        serde_json::json!({
+            "customer": {
+                "hasTags": tags.iter().map(|tag| serde_json::json!({ "tag": tag, "hasTag": true })).collect::<Vec<_>>()
+            },
+            "purchasingCompany": company_id.map(|id| serde_json::json!({ "company": { "id": id } }))
+        })
+    }
+
+    fn variant_line(variant_id: &str, quantity: i64) -> serde_json::Value {
+        product_line("1", variant_id, quantity)
+    }
+
+    fn product_line(product_id: &str, variant_id: &str, quantity: i64) -> serde_json::Value {
+        priced_line(product_id, variant_id, quantity, "10.00")
+    }
+
+    fn collection_line(variant_id: &str, quantity: i64, in_collection: bool) -> serde_json::Value {
+        let mut line = priced_line(variant_id, variant_id, quantity, "10.00");
+        line["merchandise"]["product"]["inCollection"] = serde_json::json!(in_collection);
+        line
+    }
+
+    fn priced_line(product_id: &str, variant_id: &str, quantity: i64, amount: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": format!("gid://shopify/CartLine/{}", variant_id),
+            "quantity": quantity,
+            "cost": {
+                "amountPerQuantity": {
+                    "amount": amount,
+                    "currencyCode": "USD"
+                }
+            },
+            "merchandise": {
+                "__typename": "ProductVariant",
+                "id": format!("gid://shopify/ProductVariant/{}", variant_id),
+                "product": {
+                    "id": format!("gid://shopify/Product/{}", product_id),
+                    "inCollection": false
+                }
+            }
+        })
+    }
+
+    fn percentage_discount(variant_id: &str, percentage: f64) -> run::output::Discount {
+        multi_variant_discount(&[variant_id], percentage)
+    }
+
+    fn multi_variant_discount(variant_ids: &[&str], percentage: f64) -> run::output::Discount {
+        use run::output::*;
+
+        Discount {
+            message: None,
+            targets: variant_ids
+                .iter()
+                .map(|variant_id| {
+                    Target::ProductVariant(ProductVariantTarget {
+                        id: format!("gid://shopify/ProductVariant/{}", variant_id),
+                        quantity: None,
+                    })
+                })
+                .collect(),
+            value: Value::Percentage(Percentage { value: Decimal(percentage) }),
+        }
+    }
+
+    const TIERS: &str = r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 10}, {\"minimumQuantity\": 10, \"percentage\": 15}, {\"minimumQuantity\": 25, \"percentage\": 20}]}" }"#;
 
     #[test]
     fn test_result_contains_no_discounts() -> Result<()> {
@@ -44,6 +586,16 @@ mod tests {
             run,
             r#"
                 {
+                    "cart": {
+                        "lines": [],
+                        "buyerIdentity": null
+                    },
+                    "presentmentCurrencyRate": "1.0",
+                    "localization": {
+                        "language": {
+                            "isoCode": "EN"
+                        }
+                    },
                     "discountNode": {
                         "metafield": null
                     }
@@ -58,4 +610,534 @@ mod tests {
         assert_eq!(result, expected);
         Ok(())
     }
+
+    #[test]
+    fn test_lines_get_the_tier_their_quantity_reaches() -> Result<()> {
+        use run::output::*;
+
+        let lines = serde_json::json!([
+            variant_line("1", 4),
+            variant_line("2", 5),
+            variant_line("3", 12),
+            variant_line("4", 30)
+        ]);
+        let result = run_function_with_input(run, &Input { lines, metafield: TIERS, ..Input::default() }.json())?;
+        let expected = FunctionRunResult {
+            discounts: vec![
+                percentage_discount("2", 10.0),
+                percentage_discount("3", 15.0),
+                percentage_discount("4", 20.0),
+            ],
+            discount_application_strategy: DiscountApplicationStrategy::ALL,
+        };
+
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lines_of_the_same_variant_are_combined() -> Result<()> {
+        let lines = serde_json::json!([variant_line("1", 3), variant_line("1", 2)]);
+        let result = run_function_with_input(run, &Input { lines, metafield: TIERS, ..Input::default() }.json())?;
+
+        assert_eq!(result.discounts, vec![percentage_discount("1", 10.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_variant_tiers_override_default_tiers() -> Result<()> {
+        let metafield = r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 10}], \"variants\": {\"gid://shopify/ProductVariant/2\": [{\"minimumQuantity\": 2, \"percentage\": 5}]}}" }"#;
+        let lines = serde_json::json!([variant_line("1", 3), variant_line("2", 3)]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        assert_eq!(result.discounts, vec![percentage_discount("2", 5.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_variants_are_counted_separately_by_default() -> Result<()> {
+        let lines = serde_json::json!([product_line("1", "1", 2), product_line("1", "2", 3)]);
+        let result = run_function_with_input(run, &Input { lines, metafield: TIERS, ..Input::default() }.json())?;
+
+        assert!(result.discounts.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_variants_of_the_same_product_are_aggregated() -> Result<()> {
+        let metafield = r#"{ "value": "{\"aggregateBy\": \"product\", \"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 10}]}" }"#;
+        let lines = serde_json::json!([
+            product_line("1", "1", 1),
+            product_line("1", "2", 2),
+            product_line("2", "3", 4),
+            product_line("1", "4", 2)
+        ]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        assert_eq!(result.discounts, vec![multi_variant_discount(&["1", "2", "4"], 10.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_product_tiers_override_default_tiers() -> Result<()> {
+        let metafield = r#"{ "value": "{\"aggregateBy\": \"product\", \"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 10}], \"products\": {\"gid://shopify/Product/2\": [{\"minimumQuantity\": 2, \"percentage\": 5}]}}" }"#;
+        let lines = serde_json::json!([
+            product_line("1", "1", 3),
+            product_line("2", "2", 1),
+            product_line("2", "3", 1)
+        ]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        assert_eq!(result.discounts, vec![multi_variant_discount(&["2", "3"], 5.0)]);
+        Ok(())
+    }
+
+    fn fixed_amount_discount(variant_ids: &[&str], amount: f64) -> run::output::Discount {
+        use run::output::*;
+
+        Discount {
+            value: Value::FixedAmount(FixedAmount {
+                amount: Decimal(amount),
+                applies_to_each_item: Some(true),
+            }),
+            ..multi_variant_discount(variant_ids, 0.0)
+        }
+    }
+
+    #[test]
+    fn test_amount_off_tiers_take_a_fixed_amount_off_each_item() -> Result<()> {
+        let metafield = r#"{ "value": "{\"aggregateBy\": \"product\", \"tiers\": [{\"minimumQuantity\": 5, \"amountOff\": 1.5}]}" }"#;
+        let lines = serde_json::json!([priced_line("1", "1", 3, "10.00"), priced_line("1", "2", 3, "12.00")]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        assert_eq!(result.discounts, vec![fixed_amount_discount(&["1", "2"], 1.5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unit_price_tiers_discount_each_variant_down_to_the_price() -> Result<()> {
+        let metafield = r#"{ "value": "{\"aggregateBy\": \"product\", \"tiers\": [{\"minimumQuantity\": 12, \"unitPrice\": 4.5}]}" }"#;
+        let lines = serde_json::json!([
+            priced_line("1", "1", 6, "5.00"),
+            priced_line("1", "2", 4, "4.60"),
+            priced_line("1", "3", 2, "4.00")
+        ]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        assert_eq!(
+            result.discounts,
+            vec![fixed_amount_discount(&["1"], 0.5), fixed_amount_discount(&["2"], 0.1)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unit_price_tiers_start_from_the_cheapest_line_of_a_variant() -> Result<()> {
+        let metafield = r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"unitPrice\": 9}]}" }"#;
+        let mut subscription_line = priced_line("1", "1", 2, "9.50");
+        subscription_line["id"] = serde_json::json!("gid://shopify/CartLine/2");
+        let lines = serde_json::json!([priced_line("1", "1", 3, "10.00"), subscription_line]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        // 0.50 off brings the subscription line to 9.00 instead of taking 1.00 off it
+        assert_eq!(result.discounts, vec![fixed_amount_discount(&["1"], 0.5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_amounts_are_converted_to_the_cart_currency() -> Result<()> {
+        let metafield = r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"amountOff\": 2}], \"variants\": {\"gid://shopify/ProductVariant/2\": [{\"minimumQuantity\": 5, \"unitPrice\": 4.5}]}}" }"#;
+        let lines = serde_json::json!([priced_line("1", "1", 5, "15.00"), priced_line("1", "2", 5, "7.50")]);
+        let input = Input { lines, presentment_currency_rate: "1.5", metafield, ..Input::default() };
+        let result = run_function_with_input(run, &input.json())?;
+
+        assert_eq!(
+            result.discounts,
+            vec![fixed_amount_discount(&["1"], 3.0), fixed_amount_discount(&["2"], 0.75)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_subtotal_tiers_select_the_best_tier_reached() -> Result<()> {
+        let metafield = r#"{ "value": "{\"tierBasis\": \"subtotal\", \"aggregateBy\": \"product\", \"tiers\": [{\"minimumSubtotal\": 100, \"percentage\": 10}, {\"minimumSubtotal\": 200, \"percentage\": 15}]}" }"#;
+        let lines = serde_json::json!([
+            priced_line("1", "1", 2, "60.00"),
+            priced_line("1", "2", 1, "80.00"),
+            priced_line("2", "3", 3, "33.33"),
+            priced_line("3", "4", 9, "10.00")
+        ]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        assert_eq!(result.discounts, vec![multi_variant_discount(&["1", "2"], 15.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_subtotal_minimums_are_converted_to_the_cart_currency() -> Result<()> {
+        let metafield = r#"{ "value": "{\"tierBasis\": \"subtotal\", \"tiers\": [{\"minimumSubtotal\": 200, \"percentage\": 15}]}" }"#;
+        let lines = serde_json::json!([priced_line("1", "1", 2, "149.99"), priced_line("1", "2", 2, "150.00")]);
+        let input = Input { lines, presentment_currency_rate: "1.5", metafield, ..Input::default() };
+        let result = run_function_with_input(run, &input.json())?;
+
+        assert_eq!(result.discounts, vec![percentage_discount("2", 15.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_products_in_the_collection_are_pooled() -> Result<()> {
+        let metafield = r#"{ "value": "{\"aggregateBy\": \"collection\", \"collectionIds\": [\"gid://shopify/Collection/1\"], \"tiers\": [{\"minimumQuantity\": 6, \"percentage\": 20}]}" }"#;
+        let lines = serde_json::json!([
+            collection_line("1", 2, true),
+            collection_line("2", 10, false),
+            collection_line("3", 1, true),
+            collection_line("4", 3, true)
+        ]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        assert_eq!(result.discounts, vec![multi_variant_discount(&["1", "3", "4"], 20.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collection_pool_below_the_first_tier() -> Result<()> {
+        let metafield = r#"{ "value": "{\"aggregateBy\": \"collection\", \"collectionIds\": [\"gid://shopify/Collection/1\"], \"tiers\": [{\"minimumQuantity\": 6, \"percentage\": 20}]}" }"#;
+        let lines = serde_json::json!([collection_line("1", 5, true), collection_line("2", 10, false)]);
+        let result = run_function_with_input(run, &Input { lines, metafield, ..Input::default() }.json())?;
+
+        assert!(result.discounts.is_empty());
+        Ok(())
+    }
+
+    const SEGMENTS: &str = r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 10}], \"customerTags\": [\"wholesale\", \"vip\"], \"segments\": [{\"companyIds\": [\"gid://shopify/Company/1\"], \"tiers\": [{\"minimumQuantity\": 1, \"percentage\": 30}]}, {\"customerTag\": \"wholesale\", \"anyCompany\": true, \"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 20}]}, {\"customerTag\": \"vip\", \"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 15}]}]}" }"#;
+
+    #[test]
+    fn test_buyers_get_the_first_matching_segment() -> Result<()> {
+        let discount_for = |buyer_identity: serde_json::Value| -> Result<Vec<run::output::Discount>> {
+            let lines = serde_json::json!([variant_line("1", 5)]);
+            let input = Input { lines, buyer_identity, metafield: SEGMENTS, ..Input::default() };
+            Ok(run_function_with_input(run, &input.json())?.discounts)
+        };
+
+        assert_eq!(discount_for(serde_json::Value::Null)?, vec![percentage_discount("1", 10.0)]);
+        assert_eq!(discount_for(buyer(&[], None))?, vec![percentage_discount("1", 10.0)]);
+        assert_eq!(discount_for(buyer(&["vip"], None))?, vec![percentage_discount("1", 15.0)]);
+        assert_eq!(discount_for(buyer(&["vip", "wholesale"], None))?, vec![percentage_discount("1", 20.0)]);
+        assert_eq!(
+            discount_for(buyer(&[], Some("gid://shopify/Company/2")))?,
+            vec![percentage_discount("1", 20.0)]
+        );
+        assert_eq!(
+            discount_for(buyer(&["vip"], Some("gid://shopify/Company/1")))?,
+            vec![percentage_discount("1", 30.0)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_segment_tags_must_be_query_variables() {
+        let error = Configuration::from_str(
+            r#"{"segments": [{"customerTag": "wholesale", "tiers": [{"minimumQuantity": 5, "percentage": 20}]}]}"#,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            ConfigurationError::Invalid {
+                path: "segments[0].customerTag".to_string(),
+                message: "`wholesale` must also be listed in customerTags".to_string(),
+            }
+        );
+    }
+
+    const MESSAGES: &str = r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 10}, {\"minimumQuantity\": 10, \"percentage\": 15}], \"messages\": {\"default\": {\"applied\": \"{discount} off for {minimum}+\", \"nextTier\": \"{discount} off for {minimum}+ — add {remaining} more for {nextDiscount}\"}, \"fr\": {\"applied\": \"{discount} de remise dès {minimum}\"}}}" }"#;
+
+    fn messages_for(quantity: i64, language: &str) -> Result<Vec<Option<String>>> {
+        let lines = serde_json::json!([variant_line("1", quantity)]);
+        let input = Input { lines, language, metafield: MESSAGES, ..Input::default() }.json();
+        let result = run_function_with_input(run, &input)?;
+        Ok(result.discounts.into_iter().map(|discount| discount.message).collect())
+    }
+
+    #[test]
+    fn test_message_hints_at_the_next_tier() -> Result<()> {
+        assert_eq!(messages_for(4, "EN")?, Vec::<Option<String>>::new());
+        assert_eq!(messages_for(5, "EN")?, vec![Some("10% off for 5+ — add 5 more for 15%".to_string())]);
+        assert_eq!(messages_for(12, "EN")?, vec![Some("15% off for 10+".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_is_picked_by_language() -> Result<()> {
+        assert_eq!(messages_for(7, "FR")?, vec![Some("10% de remise dès 5".to_string())]);
+        assert_eq!(messages_for(7, "DE")?, vec![Some("10% off for 5+ — add 3 more for 15%".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_subtotal_messages_show_amounts_in_the_cart_currency() -> Result<()> {
+        let metafield = r#"{ "value": "{\"tierBasis\": \"subtotal\", \"tiers\": [{\"minimumSubtotal\": 100, \"amountOff\": 2}, {\"minimumSubtotal\": 200, \"amountOff\": 5}], \"messages\": {\"default\": {\"nextTier\": \"{discount} off each — spend {remaining} more to save {nextDiscount} from {nextMinimum}\"}}}" }"#;
+        let lines = serde_json::json!([priced_line("1", "1", 8, "20.00")]);
+        let input = Input { lines, presentment_currency_rate: "1.5", metafield, ..Input::default() };
+        let result = run_function_with_input(run, &input.json())?;
+
+        assert_eq!(
+            result.discounts[0].message.as_deref(),
+            Some("3.00 off each — spend 140.00 more to save 7.50 from 300.00")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_amounts_use_the_decimals_of_the_currency() {
+        let nearest = RoundingStrategy::MidpointNearestEven;
+        assert_eq!(format_amount(rust_decimal::Decimal::new(150025, 2), "JPY", nearest), "1500");
+        assert_eq!(format_amount(rust_decimal::Decimal::new(125, 1), "USD", nearest), "12.50");
+        assert_eq!(format_amount(rust_decimal::Decimal::new(125, 1), "KWD", nearest), "12.500");
+        assert_eq!(format_amount(rust_decimal::Decimal::new(150025, 2), "JPY", RoundingStrategy::ToPositiveInfinity), "1501");
+    }
+
+    #[test]
+    fn test_fractional_converted_minimums_are_rounded_up() -> Result<()> {
+        let mut line = priced_line("1", "1", 1, "7500");
+        line["cost"]["amountPerQuantity"]["currencyCode"] = serde_json::json!("JPY");
+        // 66.67 at a rate of 150 is 10000.5 JPY, so 2500 more would fall short.
+        let metafield = r#"{ "value": "{\"tierBasis\": \"subtotal\", \"tiers\": [{\"minimumSubtotal\": 40.003, \"percentage\": 5}, {\"minimumSubtotal\": 66.67, \"percentage\": 10}], \"messages\": {\"default\": {\"nextTier\": \"{discount} off from {minimum} — spend {remaining} more for {nextDiscount} from {nextMinimum}\"}}}" }"#;
+        let input = Input {
+            lines: serde_json::json!([line]),
+            presentment_currency_rate: "150",
+            metafield,
+            ..Input::default()
+        };
+        let result = run_function_with_input(run, &input.json())?;
+
+        assert_eq!(
+            result.discounts[0].message.as_deref(),
+            Some("5% off from 6001 — spend 2501 more for 10% from 10001")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_message_without_templates() -> Result<()> {
+        let lines = serde_json::json!([variant_line("1", 5)]);
+        let result = run_function_with_input(run, &Input { lines, metafield: TIERS, ..Input::default() }.json())?;
+
+        assert_eq!(result.discounts[0].message, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_configuration_applies_no_discount() -> Result<()> {
+        use run::output::*;
+
+        let lines = serde_json::json!([variant_line("1", 50)]);
+        for metafield in [
+            r#"{ "value": "not json" }"#,
+            r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": \"five\", \"percentage\": 10}]}" }"#,
+            r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 120}]}" }"#,
+            r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"discount\": 10}]}" }"#,
+            r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 5, \"amountOff\": -1}]}" }"#,
+            r#"{ "value": "{\"tierBasis\": \"subtotal\", \"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 10}]}" }"#,
+            r#"{ "value": "{\"aggregateBy\": \"collection\", \"tiers\": [{\"minimumQuantity\": 5, \"percentage\": 10}]}" }"#,
+            r#"{ "value": "{\"segments\": [{\"anyCompany\": true, \"tiers\": [{\"minimumQuantity\": 0, \"percentage\": 10}]}]}" }"#,
+        ] {
+            let input = Input { lines: lines.clone(), metafield, ..Input::default() };
+            let result = run_function_with_input(run, &input.json())?;
+            let expected = FunctionRunResult {
+                discounts: vec![],
+                discount_application_strategy: DiscountApplicationStrategy::FIRST,
+            };
+
+            assert_eq!(result, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_amounts_too_large_for_the_cart_currency_apply_no_discount() -> Result<()> {
+        use run::output::*;
+
+        let lines = serde_json::json!([variant_line("1", 50)]);
+        for metafield in [
+            r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 1, \"amountOff\": 7.9e28}]}" }"#,
+            r#"{ "value": "{\"tiers\": [{\"minimumQuantity\": 1, \"unitPrice\": 7.9e28}]}" }"#,
+            r#"{ "value": "{\"tierBasis\": \"subtotal\", \"tiers\": [{\"minimumSubtotal\": 7.9e28, \"percentage\": 10}]}" }"#,
+        ] {
+            let input = Input {
+                lines: lines.clone(),
+                presentment_currency_rate: "1.5",
+                metafield,
+                ..Input::default()
+            };
+            let result = run_function_with_input(run, &input.json())?;
+            let expected = FunctionRunResult {
+                discounts: vec![],
+                discount_application_strategy: DiscountApplicationStrategy::FIRST,
+            };
+
+            assert_eq!(result, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_configuration_errors_point_at_the_field() {
+        let error = Configuration::from_str(r#"{"tiers": [{"minimumQuantity": 5, "percentage": 10}, {"minimumQuantity": "ten", "percentage": 15}]}"#)
+            .unwrap_err();
+        assert!(matches!(&error, ConfigurationError::Malformed { path, .. } if path == "tiers[1].minimumQuantity"));
+
+        let error = Configuration::from_str(r#"{"aggregateBy": "shop"}"#).unwrap_err();
+        assert!(matches!(&error, ConfigurationError::Malformed { path, .. } if path == "aggregateBy"));
+
+        let error = Configuration::from_str(r#"{"variants": {"gid://shopify/ProductVariant/1": [{"minimumQuantity": 0, "percentage": 10}]}}"#)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ConfigurationError::Invalid {
+                path: "variants.gid://shopify/ProductVariant/1[0].minimumQuantity".to_string(),
+                message: "must be at least 1".to_string(),
+            }
+        );
+
+        let error = Configuration::from_str(r#"{"aggregateby": "collection"}"#).unwrap_err();
+        assert!(matches!(&error, ConfigurationError::Malformed { path, .. } if path == "aggregateby"));
+
+        let error = Configuration::from_str(r#"{"tiers": [{"minimumQuantity": 5, "percentage": "10"}]}"#).unwrap_err();
+        assert!(matches!(&error, ConfigurationError::Malformed { path, .. } if path == "tiers[0]"));
+
+        let error = Configuration::from_str(r#"{"tiers": [{"minimumQuantity": 5, "minimumQuantiy": 10, "percentage": 10}]}"#)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ConfigurationError::Invalid {
+                path: "tiers[0].minimumQuantiy".to_string(),
+                message: "is not a known setting".to_string(),
+            }
+        );
+
+        let error = Configuration::from_str(r#"{"segments": [{"tiers": [{"minimumQuantity": 5, "percentage": 10}]}]}"#).unwrap_err();
+        assert_eq!(
+            error,
+            ConfigurationError::Invalid {
+                path: "segments[0]".to_string(),
+                message: "must set one of customerTag, anyCompany, companyIds".to_string(),
+            }
+        );
+    }
+
+    /// Runs every fixture in `fixtures/`, see the README.
+    #[test]
+    fn test_golden_fixtures() -> Result<()> {
+        use run::output::*;
+
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let update = env::var_os("UPDATE_FIXTURES").is_some();
+        let mut cases = 0;
+
+        for entry in fs::read_dir(&fixtures)? {
+            let input_path = entry?.path();
+            let case = match input_path.file_name().and_then(|name| name.to_str()).and_then(|name| name.strip_suffix(".input.json")) {
+                Some(case) => case.to_string(),
+                None => continue,
+            };
+            let output_path = fixtures.join(format!("{}.output.json", case));
+
+            let input = fs::read_to_string(&input_path)?;
+            let result: FunctionRunResult = run_function_with_input(run, &input)?;
+            let actual = serde_json::to_value(&result)?;
+
+            if update {
+                fs::write(&output_path, serde_json::to_string_pretty(&actual)? + "\n")?;
+            } else {
+                let expected: serde_json::Value = serde_json::from_str(&fs::read_to_string(&output_path)?)?;
+                assert_eq!(actual, expected, "fixture {} does not match {}", case, output_path.display());
+            }
+            cases += 1;
+        }
+
+        assert!(cases > 0, "no fixtures found in {}", fixtures.display());
+        Ok(())
+    }
+
+    /// The schema derived from `Configuration`, plus the rules that depend on
+    /// other fields and so can't be derived from the types: collection pools need
+    /// `collectionIds`, segments need a way to match buyers, and every tier needs
+    /// the minimum of the configured `tierBasis`.
+    fn configuration_schema() -> serde_json::Value {
+        use serde_json::json;
+
+        let mut schema = serde_json::to_value(schemars::schema_for!(Configuration)).unwrap();
+        let tier_tables = |tier: &str| {
+            let tiers = json!({ "items": { "$ref": format!("#/definitions/{}", tier) } });
+            let table = json!({
+                "properties": {
+                    "tiers": tiers,
+                    "variants": { "additionalProperties": tiers },
+                    "products": { "additionalProperties": tiers }
+                }
+            });
+            let mut tables = table.clone();
+            tables["properties"]["segments"] = json!({ "items": table });
+            tables
+        };
+
+        schema["allOf"] = json!([
+            {
+                "if": { "required": ["aggregateBy"], "properties": { "aggregateBy": { "enum": ["collection"] } } },
+                "then": { "required": ["collectionIds"], "properties": { "collectionIds": { "minItems": 1 } } }
+            },
+            {
+                "if": { "required": ["tierBasis"], "properties": { "tierBasis": { "enum": ["subtotal"] } } },
+                "then": tier_tables("SubtotalTier"),
+                "else": tier_tables("QuantityTier")
+            }
+        ]);
+        // serde can't deny unknown fields next to the flattened `TierValue`, and
+        // schemars drops `deny_unknown_fields` there too.
+        schema["definitions"]["Tier"]["additionalProperties"] = json!(false);
+        schema["definitions"]["QuantityTier"] = json!({
+            "required": ["minimumQuantity"],
+            "properties": { "minimumQuantity": { "minimum": 1 } }
+        });
+        schema["definitions"]["SubtotalTier"] = json!({
+            "required": ["minimumSubtotal"],
+            "properties": { "minimumSubtotal": { "type": "number", "exclusiveMinimum": 0 } }
+        });
+        schema["definitions"]["Segment"]["anyOf"] = json!([
+            { "required": ["customerTag"], "properties": { "customerTag": { "type": "string" } } },
+            { "required": ["anyCompany"], "properties": { "anyCompany": { "enum": [true] } } },
+            { "required": ["companyIds"], "properties": { "companyIds": { "minItems": 1 } } }
+        ]);
+        schema
+    }
+
+    #[test]
+    fn test_configuration_schema_is_up_to_date() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/configuration.schema.json");
+        let schema = serde_json::to_string_pretty(&configuration_schema()).unwrap() + "\n";
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(path, &schema).unwrap();
+        }
+
+        let expected = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            schema == expected,
+            "configuration.schema.json is out of date, rerun with UPDATE_SCHEMA=1"
+        );
+    }
+
+    #[test]
+    fn test_custom_products_are_never_discounted() -> Result<()> {
+        let lines = serde_json::json!([{
+            "id": "gid://shopify/CartLine/1",
+            "quantity": 50,
+            "cost": { "amountPerQuantity": { "amount": "10.00", "currencyCode": "USD" } },
+            "merchandise": { "__typename": "CustomProduct" }
+        }]);
+        let result = run_function_with_input(run, &Input { lines, metafield: TIERS, ..Input::default() }.json())?;
+
+        assert!(result.discounts.is_empty());
+        Ok(())
+    }
 }
@@ -0,0 +1,316 @@
+use serde_json::Value;
+
+/// A part of a configuration that breaks the schema. `path` points at it, e.g.
+/// `tiers[1].percentage`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SchemaError {
+    pub path: String,
+    pub message: String,
+}
+
+/// Checks `value` against a JSON schema generated from the Rust `Configuration`
+/// struct (`configuration.schema.json`). Only the draft-07 keywords that schema
+/// uses are supported.
+///
+/// This mirrors `validate` in the settings UI's `src/schema.js`, so the function
+/// and the UI report the same errors for the same configuration.
+pub fn validate(value: &Value, schema: &Value) -> Vec<SchemaError> {
+    validate_at(value, schema, schema, "")
+}
+
+fn validate_at(value: &Value, schema: &Value, root: &Value, path: &str) -> Vec<SchemaError> {
+    let schema = resolve(schema, root);
+    let mut errors = Vec::new();
+    let fail = |errors: &mut Vec<SchemaError>, message: String| {
+        errors.push(SchemaError {
+            path: if path.is_empty() { "configuration".to_string() } else { path.to_string() },
+            message,
+        })
+    };
+
+    if let Some(types) = schema.get("type") {
+        let types = list(types);
+        if !types.iter().any(|name| matches_type(value, name)) {
+            fail(&mut errors, format!("must be {}", join_values(&types, " or ")));
+            return errors;
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            fail(&mut errors, format!("must be one of {}", join_values(allowed, ", ")));
+        }
+    }
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+            if number < minimum {
+                fail(&mut errors, format!("must be at least {}", minimum));
+            }
+        }
+        if let Some(exclusive_minimum) = schema.get("exclusiveMinimum").and_then(Value::as_f64) {
+            if number <= exclusive_minimum {
+                fail(&mut errors, format!("must be greater than {}", exclusive_minimum));
+            }
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+            if number > maximum {
+                fail(&mut errors, format!("must be at most {}", maximum));
+            }
+        }
+    }
+    if let (Value::Array(items), Some(min_items)) = (value, schema.get("minItems").and_then(Value::as_u64)) {
+        if (items.len() as u64) < min_items {
+            fail(&mut errors, format!("must have at least {} item(s)", min_items));
+        }
+    }
+
+    for branch in schema.get("allOf").and_then(Value::as_array).into_iter().flatten() {
+        errors.extend(validate_at(value, branch, root, path));
+    }
+    if let Some(Value::Array(branches)) = schema.get("anyOf") {
+        errors.extend(validate_any_of(value, branches, root, path));
+    }
+    if let Some(Value::Array(branches)) = schema.get("oneOf") {
+        errors.extend(validate_one_of(value, branches, root, path));
+    }
+    if let Some(condition) = schema.get("if") {
+        let branch = if validate_at(value, condition, root, path).is_empty() {
+            schema.get("then")
+        } else {
+            schema.get("else")
+        };
+        if let Some(branch) = branch {
+            errors.extend(validate_at(value, branch, root, path));
+        }
+    }
+
+    if let Value::Object(object) = value {
+        for key in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+            let key = key.as_str().unwrap_or_default();
+            if !object.contains_key(key) {
+                errors.push(SchemaError { path: join(path, key), message: "is required".to_string() });
+            }
+        }
+        for (key, property) in object {
+            match (schema.get("properties").and_then(|properties| properties.get(key)), schema.get("additionalProperties")) {
+                (Some(property_schema), _) => errors.extend(validate_at(property, property_schema, root, &join(path, key))),
+                (None, Some(additional @ Value::Object(_))) => {
+                    errors.extend(validate_at(property, additional, root, &join(path, key)))
+                }
+                (None, Some(Value::Bool(false))) => {
+                    errors.push(SchemaError { path: join(path, key), message: "is not a known setting".to_string() })
+                }
+                _ => {}
+            }
+        }
+    }
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            errors.extend(validate_at(item, item_schema, root, &format!("{}[{}]", path, index)));
+        }
+    }
+
+    errors
+}
+
+/// The values allowed by a `oneOf` of single-value enums, such as `aggregateBy`.
+fn enum_values(branches: &[Value], root: &Value) -> Vec<Value> {
+    branches
+        .iter()
+        .flat_map(|branch| resolve(branch, root).get("enum").and_then(Value::as_array).cloned().unwrap_or_default())
+        .collect()
+}
+
+/// The fields a `oneOf` or `anyOf` of required fields chooses between, such as a
+/// tier's `percentage`, `amountOff` or `unitPrice`.
+fn required_alternatives(branches: &[Value], root: &Value) -> Vec<Value> {
+    branches
+        .iter()
+        .flat_map(|branch| resolve(branch, root).get("required").and_then(Value::as_array).cloned().unwrap_or_default())
+        .collect()
+}
+
+fn validate_any_of(value: &Value, branches: &[Value], root: &Value, path: &str) -> Vec<SchemaError> {
+    if branches.iter().any(|branch| validate_at(value, branch, root, path).is_empty()) {
+        return vec![];
+    }
+
+    let alternatives = required_alternatives(branches, root);
+    let message = if alternatives.is_empty() {
+        format!("must be one of {}", join_values(&enum_values(branches, root), ", "))
+    } else {
+        format!("must set one of {}", join_values(&alternatives, ", "))
+    };
+    vec![SchemaError { path: if path.is_empty() { "configuration".to_string() } else { path.to_string() }, message }]
+}
+
+fn validate_one_of(value: &Value, branches: &[Value], root: &Value, path: &str) -> Vec<SchemaError> {
+    let results: Vec<Vec<SchemaError>> = branches.iter().map(|branch| validate_at(value, branch, root, path)).collect();
+    let matching = results.iter().filter(|errors| errors.is_empty()).count();
+    if matching == 1 {
+        return vec![];
+    }
+
+    // Report the errors of the branch the value was meant for, e.g. a tier with
+    // a `percentage` above 100, rather than a generic message.
+    let chosen = branches.iter().position(|branch| {
+        resolve(branch, root)
+            .get("required")
+            .and_then(Value::as_array)
+            .map_or(false, |required| required.iter().any(|key| key.as_str().and_then(|key| value.get(key)).is_some()))
+    });
+    if let (0, Some(chosen)) = (matching, chosen) {
+        return results[chosen].clone();
+    }
+
+    let alternatives = required_alternatives(branches, root);
+    let message = if alternatives.is_empty() {
+        format!("must be one of {}", join_values(&enum_values(branches, root), ", "))
+    } else {
+        format!("must set exactly one of {}", join_values(&alternatives, ", "))
+    };
+    vec![SchemaError { path: if path.is_empty() { "configuration".to_string() } else { path.to_string() }, message }]
+}
+
+fn resolve<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    match schema.get("$ref").and_then(Value::as_str) {
+        Some(reference) => {
+            let name = reference.trim_start_matches("#/definitions/");
+            resolve(&root["definitions"][name], root)
+        }
+        None => schema,
+    }
+}
+
+fn matches_type(value: &Value, name: &Value) -> bool {
+    match name.as_str().unwrap_or_default() {
+        "integer" => value.as_f64().map_or(false, |number| number.fract() == 0.0),
+        "number" => value.is_number(),
+        "null" => value.is_null(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        _ => false,
+    }
+}
+
+fn list(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Array(values) => values.clone(),
+        value => vec![value.clone()],
+    }
+}
+
+fn join_values(values: &[Value], separator: &str) -> String {
+    values
+        .iter()
+        .map(|value| match value {
+            Value::String(string) => string.clone(),
+            value => value.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn errors(value: Value, schema: Value) -> Vec<(String, String)> {
+        validate(&value, &schema).into_iter().map(|error| (error.path, error.message)).collect()
+    }
+
+    fn error(path: &str, message: &str) -> Vec<(String, String)> {
+        vec![(path.to_string(), message.to_string())]
+    }
+
+    /// Every case in `configuration.cases.json` gives the errors listed with it.
+    /// The settings UI runs the same file through `schema.js`, so the two
+    /// validators can't drift apart unnoticed.
+    #[test]
+    fn test_configuration_cases() {
+        let read = |file: &str| -> Value {
+            let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file);
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+        };
+        let schema = read("configuration.schema.json");
+
+        for case in read("configuration.cases.json").as_array().unwrap() {
+            let mut actual = errors(case["configuration"].clone(), schema.clone());
+            actual.sort();
+            let mut expected: Vec<(String, String)> = case["errors"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|error| (error["path"].as_str().unwrap().to_string(), error["message"].as_str().unwrap().to_string()))
+                .collect();
+            expected.sort();
+
+            assert_eq!(actual, expected, "case `{}`", case["name"]);
+        }
+    }
+
+    #[test]
+    fn test_bounds_and_unknown_fields_point_at_the_field() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "tiers": { "type": "array", "items": { "$ref": "#/definitions/Tier" } } },
+            "additionalProperties": false,
+            "definitions": {
+                "Tier": { "type": "object", "properties": { "percentage": { "type": "number", "maximum": 100 } } }
+            }
+        });
+
+        assert_eq!(errors(json!({"tiers": [{"percentage": 10}]}), schema.clone()), vec![]);
+        assert_eq!(errors(json!({"tiers": [{}, {"percentage": 120}]}), schema.clone()), error("tiers[1].percentage", "must be at most 100"));
+        assert_eq!(errors(json!({"tiers": [{"percentage": "10"}]}), schema.clone()), error("tiers[0].percentage", "must be number"));
+        assert_eq!(errors(json!({"tier": []}), schema), error("tier", "is not a known setting"));
+    }
+
+    #[test]
+    fn test_one_of_reports_the_chosen_branch() {
+        let schema = json!({
+            "oneOf": [
+                { "required": ["percentage"], "properties": { "percentage": { "maximum": 100 } } },
+                { "required": ["amountOff"] }
+            ]
+        });
+
+        assert_eq!(errors(json!({"percentage": 10}), schema.clone()), vec![]);
+        assert_eq!(errors(json!({"percentage": 120}), schema.clone()), error("percentage", "must be at most 100"));
+        assert_eq!(errors(json!({}), schema.clone()), error("configuration", "must set exactly one of percentage, amountOff"));
+        assert_eq!(
+            errors(json!({"percentage": 10, "amountOff": 1}), schema),
+            error("configuration", "must set exactly one of percentage, amountOff")
+        );
+    }
+
+    #[test]
+    fn test_any_of_and_conditions() {
+        let schema = json!({
+            "anyOf": [{ "required": ["customerTag"] }, { "required": ["companyIds"], "properties": { "companyIds": { "minItems": 1 } } }],
+            "if": { "required": ["tierBasis"], "properties": { "tierBasis": { "enum": ["subtotal"] } } },
+            "then": { "required": ["minimumSubtotal"] },
+            "else": { "required": ["minimumQuantity"] }
+        });
+
+        assert_eq!(errors(json!({"customerTag": "vip", "minimumQuantity": 5}), schema.clone()), vec![]);
+        assert_eq!(
+            errors(json!({"companyIds": [], "minimumQuantity": 5}), schema.clone()),
+            error("configuration", "must set one of customerTag, companyIds")
+        );
+        assert_eq!(
+            errors(json!({"customerTag": "vip", "tierBasis": "subtotal", "minimumQuantity": 5}), schema),
+            error("minimumSubtotal", "is required")
+        );
+    }
+}
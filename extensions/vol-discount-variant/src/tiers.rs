@@ -0,0 +1,297 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::str::FromStr;
+
+/// One step of a volume discount, e.g. 10% off from 5 units. Only the minimum
+/// matching the configured `TierBasis` is used.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct Tier {
+    #[serde(default)]
+    pub minimum_quantity: i64,
+    /// Subtotal in the shop's currency, e.g. `200` for "spend $200".
+    #[serde(default, deserialize_with = "optional_number")]
+    #[cfg_attr(test, schemars(with = "Option<f64>"))]
+    pub minimum_subtotal: Option<Decimal>,
+    #[serde(flatten)]
+    pub value: TierValue,
+}
+
+/// What the lines of a group have to reach for a tier to apply.
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum TierBasis {
+    /// Number of items, compared with `minimumQuantity`.
+    #[default]
+    Quantity,
+    /// Sum of `amountPerQuantity * quantity`, compared with `minimumSubtotal`.
+    Subtotal,
+}
+
+/// How far the lines of a group got. `subtotal` is in the cart's currency.
+#[derive(Default, PartialEq, Clone, Copy, Debug)]
+pub struct Progress {
+    pub quantity: i64,
+    pub subtotal: Decimal,
+}
+
+impl TierBasis {
+    /// The minimum of `tier`, with subtotals converted into the cart's currency,
+    /// or `None` if the converted subtotal is too large to represent.
+    pub fn minimum(&self, tier: &Tier, presentment_currency_rate: Decimal) -> Option<Decimal> {
+        match self {
+            TierBasis::Quantity => Some(Decimal::from(tier.minimum_quantity)),
+            TierBasis::Subtotal => tier.minimum_subtotal.unwrap_or_default().checked_mul(presentment_currency_rate),
+        }
+    }
+
+    pub fn reached(&self, progress: &Progress) -> Decimal {
+        match self {
+            TierBasis::Quantity => Decimal::from(progress.quantity),
+            TierBasis::Subtotal => progress.subtotal,
+        }
+    }
+}
+
+/// What a tier takes off each item. Amounts are in the shop's currency and are
+/// converted into the cart's currency before they're applied.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum TierValue {
+    /// Percentage off each item, e.g. `10` for 10% off.
+    Percentage(#[serde(deserialize_with = "number")] Decimal),
+    /// Fixed amount off each item, e.g. `1.50`.
+    AmountOff(#[serde(deserialize_with = "number")] Decimal),
+    /// Final price of each item, e.g. `4.50` for "buy 12+ and each is $4.50".
+    UnitPrice(#[serde(deserialize_with = "number")] Decimal),
+}
+
+/// Reads a decimal from a JSON number only, as the schema types it, rather than
+/// also from a string, which would skip the schema's bounds. Goes through the
+/// number's shortest string form, like rust_decimal's own visitor.
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    Decimal::from_str(&value.to_string()).map_err(serde::de::Error::custom)
+}
+
+fn optional_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Decimal>, D::Error> {
+    match Option::<f64>::deserialize(deserializer)? {
+        Some(value) => Decimal::from_str(&value.to_string()).map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+impl TierValue {
+    /// Name of the field holding the value in the configuration.
+    pub fn field(&self) -> &'static str {
+        match self {
+            TierValue::Percentage(_) => "percentage",
+            TierValue::AmountOff(_) => "amountOff",
+            TierValue::UnitPrice(_) => "unitPrice",
+        }
+    }
+
+    /// This value with fixed amounts converted into the cart's currency, or `None`
+    /// if the converted amount is too large to represent.
+    pub fn in_currency(&self, presentment_currency_rate: Decimal) -> Option<TierValue> {
+        match self {
+            TierValue::Percentage(percentage) => Some(TierValue::Percentage(*percentage)),
+            TierValue::AmountOff(amount) => amount.checked_mul(presentment_currency_rate).map(TierValue::AmountOff),
+            TierValue::UnitPrice(price) => price.checked_mul(presentment_currency_rate).map(TierValue::UnitPrice),
+        }
+    }
+}
+
+/// Written out by hand because the derived schema of a flattened enum forbids
+/// any field besides the value, including `minimumQuantity`. The bounds sit next
+/// to `Tier`'s own fields, so `additionalProperties` sees every known field.
+#[cfg(test)]
+impl schemars::JsonSchema for TierValue {
+    fn schema_name() -> String {
+        "TierValue".to_string()
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        serde_json::from_value(serde_json::json!({
+            "properties": {
+                "percentage": {
+                    "description": "Percentage off each item, e.g. `10` for 10% off.",
+                    "type": "number",
+                    "exclusiveMinimum": 0,
+                    "maximum": 100
+                },
+                "amountOff": {
+                    "description": "Fixed amount off each item, e.g. `1.50`.",
+                    "type": "number",
+                    "exclusiveMinimum": 0
+                },
+                "unitPrice": {
+                    "description": "Final price of each item, e.g. `4.50`.",
+                    "type": "number",
+                    "minimum": 0
+                }
+            },
+            "oneOf": [
+                { "required": ["percentage"] },
+                { "required": ["amountOff"] },
+                { "required": ["unitPrice"] }
+            ]
+        }))
+        .unwrap()
+    }
+}
+
+/// Picks the tier with the highest minimum that `progress` reaches. Tiers may be
+/// listed in any order. A tier whose minimum is too large to represent in the
+/// cart's currency is never reached.
+pub fn best_tier<'a>(
+    tiers: &'a [Tier],
+    basis: TierBasis,
+    progress: &Progress,
+    presentment_currency_rate: Decimal,
+) -> Option<&'a Tier> {
+    let reached = basis.reached(progress);
+    tiers
+        .iter()
+        .filter_map(|tier| Some((basis.minimum(tier, presentment_currency_rate)?, tier)))
+        .filter(|(minimum, _)| *minimum <= reached)
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tier)| tier)
+}
+
+/// Picks the tier with the lowest minimum that `progress` hasn't reached yet.
+pub fn next_tier<'a>(
+    tiers: &'a [Tier],
+    basis: TierBasis,
+    progress: &Progress,
+    presentment_currency_rate: Decimal,
+) -> Option<&'a Tier> {
+    let reached = basis.reached(progress);
+    tiers
+        .iter()
+        .filter_map(|tier| Some((basis.minimum(tier, presentment_currency_rate)?, tier)))
+        .filter(|(minimum, _)| *minimum > reached)
+        .min_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tier)| tier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn tier(minimum_quantity: i64, percentage: Decimal) -> Tier {
+        Tier {
+            minimum_quantity,
+            minimum_subtotal: None,
+            value: TierValue::Percentage(percentage),
+        }
+    }
+
+    fn subtotal_tier(minimum_subtotal: Decimal, percentage: Decimal) -> Tier {
+        Tier { minimum_subtotal: Some(minimum_subtotal), ..tier(0, percentage) }
+    }
+
+    fn best_quantity_tier(tiers: &[Tier], quantity: i64) -> Option<&Tier> {
+        best_tier(tiers, TierBasis::Quantity, &Progress { quantity, subtotal: Decimal::ZERO }, Decimal::ONE)
+    }
+
+    #[test]
+    fn test_best_tier_matches_highest_reached_quantity() {
+        let tiers = vec![tier(10, dec!(15)), tier(5, dec!(10)), tier(25, dec!(20))];
+
+        assert_eq!(best_quantity_tier(&tiers, 4), None);
+        assert_eq!(best_quantity_tier(&tiers, 5), Some(&tier(5, dec!(10))));
+        assert_eq!(best_quantity_tier(&tiers, 24), Some(&tier(10, dec!(15))));
+        assert_eq!(best_quantity_tier(&tiers, 100), Some(&tier(25, dec!(20))));
+    }
+
+    #[test]
+    fn test_next_tier_is_the_lowest_unreached_tier() {
+        let tiers = vec![tier(25, dec!(20)), tier(5, dec!(10)), tier(10, dec!(15))];
+        let next = |quantity| next_tier(&tiers, TierBasis::Quantity, &Progress { quantity, subtotal: Decimal::ZERO }, Decimal::ONE);
+
+        assert_eq!(next(1), Some(&tier(5, dec!(10))));
+        assert_eq!(next(5), Some(&tier(10, dec!(15))));
+        assert_eq!(next(24), Some(&tier(25, dec!(20))));
+        assert_eq!(next(25), None);
+    }
+
+    #[test]
+    fn test_best_tier_without_tiers() {
+        assert_eq!(best_quantity_tier(&[], 100), None);
+    }
+
+    #[test]
+    fn test_best_tier_matches_highest_reached_subtotal() {
+        let tiers = vec![subtotal_tier(dec!(200), dec!(15)), subtotal_tier(dec!(100), dec!(10))];
+        let progress = |subtotal| Progress { quantity: 1, subtotal };
+
+        assert_eq!(best_tier(&tiers, TierBasis::Subtotal, &progress(dec!(99.99)), Decimal::ONE), None);
+        assert_eq!(
+            best_tier(&tiers, TierBasis::Subtotal, &progress(dec!(200)), Decimal::ONE),
+            Some(&subtotal_tier(dec!(200), dec!(15)))
+        );
+        // 200 in the shop's currency is 300 in the cart's.
+        assert_eq!(
+            best_tier(&tiers, TierBasis::Subtotal, &progress(dec!(299)), dec!(1.5)),
+            Some(&subtotal_tier(dec!(100), dec!(10)))
+        );
+    }
+
+    #[test]
+    fn test_minimum_subtotal_too_large_for_the_cart_currency_is_never_reached() {
+        let tiers = vec![subtotal_tier(Decimal::MAX, dec!(50)), subtotal_tier(dec!(100), dec!(10))];
+        let progress = Progress { quantity: 1, subtotal: Decimal::MAX };
+
+        assert_eq!(TierBasis::Subtotal.minimum(&tiers[0], dec!(1.5)), None);
+        assert_eq!(
+            best_tier(&tiers, TierBasis::Subtotal, &progress, dec!(1.5)),
+            Some(&subtotal_tier(dec!(100), dec!(10)))
+        );
+        assert_eq!(next_tier(&tiers, TierBasis::Subtotal, &Progress { quantity: 1, subtotal: dec!(200) }, dec!(1.5)), None);
+    }
+
+    #[test]
+    fn test_tier_value_in_currency() {
+        assert_eq!(TierValue::Percentage(dec!(10)).in_currency(dec!(1.5)), Some(TierValue::Percentage(dec!(10))));
+        assert_eq!(TierValue::AmountOff(dec!(2)).in_currency(dec!(1.5)), Some(TierValue::AmountOff(dec!(3))));
+        assert_eq!(TierValue::UnitPrice(Decimal::MAX).in_currency(dec!(1.5)), None);
+    }
+
+    #[test]
+    fn test_tier_values_are_read_from_their_field() {
+        let tiers: Vec<Tier> = serde_json::from_str(
+            r#"[
+                {"minimumQuantity": 5, "percentage": 10},
+                {"minimumQuantity": 10, "amountOff": 1.5},
+                {"minimumQuantity": 12, "unitPrice": 4.50}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            tiers.into_iter().map(|tier| tier.value).collect::<Vec<_>>(),
+            vec![
+                TierValue::Percentage(dec!(10)),
+                TierValue::AmountOff(dec!(1.5)),
+                TierValue::UnitPrice(dec!(4.50)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tier_values_must_be_numbers() {
+        let error = serde_json::from_str::<Tier>(r#"{"minimumQuantity": 12, "unitPrice": "4.50"}"#).unwrap_err();
+        assert!(error.to_string().contains("invalid type: string"), "{}", error);
+
+        let error = serde_json::from_str::<Tier>(r#"{"minimumSubtotal": "200", "percentage": 10}"#).unwrap_err();
+        assert!(error.to_string().contains("invalid type: string"), "{}", error);
+    }
+}